@@ -0,0 +1,6 @@
+-- Store the detected language of each website so results can be filtered by language.
+-- Existing rows were indexed when only English was accepted.
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS lang TEXT;
+UPDATE websites SET lang = 'eng' WHERE lang IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_websites_lang ON websites (lang);
@@ -0,0 +1,6 @@
+-- Record where each keyword occurs in a page so phrase and proximity queries are possible.
+-- Positions are word offsets in the page body, counting stopwords, stored delta-encoded:
+-- the first element is the first position and every later element is the gap to the previous one.
+-- A running sum over the array gives the absolute positions back.
+-- Rows indexed before this migration have no positions until their page is crawled again.
+ALTER TABLE website_keywords ADD COLUMN IF NOT EXISTS positions INT[] NOT NULL DEFAULT '{}';
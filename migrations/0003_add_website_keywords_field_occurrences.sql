@@ -0,0 +1,9 @@
+-- Count keyword occurrences per field so ranking can weight title, description, headings,
+-- URL and body matches differently (BM25F). keyword_occurrences keeps counting the body only,
+-- so a keyword that appears only in the title has keyword_occurrences = 0.
+-- Rows indexed before this migration have zero field counts until their page is crawled again.
+ALTER TABLE website_keywords
+    ADD COLUMN IF NOT EXISTS title_occurrences INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS description_occurrences INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS heading_occurrences INT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS url_occurrences INT NOT NULL DEFAULT 0;
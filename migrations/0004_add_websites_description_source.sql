@@ -0,0 +1,5 @@
+-- Record where each description came from: the meta description (meta), og:description (og),
+-- twitter:description (twitter) or a snippet of the page text (snippet).
+-- Descriptions stored before this migration were copies of the title, so their source is left NULL
+-- until the page is crawled again.
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS description_source TEXT;
@@ -0,0 +1,30 @@
+-- Keep the anchor text, title and rel of every link, and credit the analyzed anchor text
+-- to the linked page once it is indexed.
+ALTER TABLE website_links
+    ADD COLUMN IF NOT EXISTS target_website_id INT REFERENCES websites(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS anchor_text TEXT,
+    ADD COLUMN IF NOT EXISTS title TEXT,
+    ADD COLUMN IF NOT EXISTS rel TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_website_links_target_id ON website_links (target_website_id);
+
+-- Resolve links to pages that are already indexed
+UPDATE website_links
+SET target_website_id = websites.id
+FROM websites
+WHERE websites.url = website_links.target_website AND website_links.target_website_id IS NULL;
+
+-- Analyzed anchor text of each link. Deleting a link removes its anchor keywords.
+CREATE TABLE IF NOT EXISTS link_keywords (
+    id BIGSERIAL PRIMARY KEY,
+    link_id INT NOT NULL REFERENCES website_links(id) ON DELETE CASCADE,
+    keyword_id INT NOT NULL REFERENCES keywords(id),
+    occurrences INT NOT NULL,
+    CONSTRAINT unique_link_keyword UNIQUE (link_id, keyword_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_link_keywords_link_id ON link_keywords (link_id);
+
+-- Anchor text occurrences credited to the target website. Keywords that only appear in anchor
+-- text have a row with zero occurrences in every other field.
+ALTER TABLE website_keywords ADD COLUMN IF NOT EXISTS anchor_occurrences INT NOT NULL DEFAULT 0;
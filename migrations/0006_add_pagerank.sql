@@ -0,0 +1,8 @@
+-- PageRank scores written by `cargo run -- pagerank`
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS pagerank DOUBLE PRECISION;
+
+CREATE TABLE IF NOT EXISTS host_pagerank (
+    host TEXT PRIMARY KEY,
+    pagerank DOUBLE PRECISION NOT NULL,
+    page_count INT NOT NULL
+);
@@ -0,0 +1,6 @@
+-- Links that shouldn't count as endorsements, excluded from PageRank
+ALTER TABLE website_links ADD COLUMN IF NOT EXISTS nofollow BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE website_links
+SET nofollow = TRUE
+WHERE rel ~ '(^|\s)(nofollow|ugc|sponsored)(\s|$)';
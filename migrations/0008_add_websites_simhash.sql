@@ -0,0 +1,8 @@
+-- Near-duplicate detection. Existing rows get a fingerprint when their files are processed again.
+ALTER TABLE websites
+    ADD COLUMN IF NOT EXISTS simhash BIGINT,
+    ADD COLUMN IF NOT EXISTS simhash_bands INT[],
+    ADD COLUMN IF NOT EXISTS duplicate_of INT REFERENCES websites(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_websites_simhash_bands ON websites USING GIN (simhash_bands);
+CREATE INDEX IF NOT EXISTS idx_websites_duplicate_of ON websites (duplicate_of);
@@ -0,0 +1,12 @@
+-- Exact duplicate suppression with WARC-Payload-Digest
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS payload_digest TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_websites_payload_digest ON websites (payload_digest);
+
+CREATE TABLE IF NOT EXISTS website_aliases (
+    id SERIAL PRIMARY KEY,
+    url TEXT UNIQUE NOT NULL,
+    website_id INT NOT NULL REFERENCES websites(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_website_aliases_website_id ON website_aliases (website_id);
@@ -0,0 +1,13 @@
+-- Redirects and error responses found in WARC response records
+CREATE TABLE IF NOT EXISTS redirects (
+    id SERIAL PRIMARY KEY,
+    source_url TEXT UNIQUE NOT NULL,
+    target_url TEXT NOT NULL,
+    status_code INT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS url_statuses (
+    id SERIAL PRIMARY KEY,
+    url TEXT UNIQUE NOT NULL,
+    status_code INT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- Tag tracked files as warc, wet or wat, and mark the ones read from LOCAL_WARC_INPUT.
+-- The files table is created by the crawler on first run.
+ALTER TABLE IF EXISTS files ADD COLUMN IF NOT EXISTS kind TEXT NOT NULL DEFAULT 'warc';
+ALTER TABLE IF EXISTS files ADD COLUMN IF NOT EXISTS is_local BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The kind of file each website was stored from. Every existing website came from a WARC file.
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS input_kind TEXT NOT NULL DEFAULT 'warc';
@@ -0,0 +1,6 @@
+-- Ledger of the records of each file that are already stored, so interrupted files resume without counting a page twice
+CREATE TABLE IF NOT EXISTS ingested_records (
+    file_name TEXT NOT NULL,
+    record_id TEXT NOT NULL,
+    PRIMARY KEY (file_name, record_id)
+);
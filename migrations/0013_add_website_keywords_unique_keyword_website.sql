@@ -0,0 +1,11 @@
+-- One row per keyword and website, so anchor text credits can be upserted
+DELETE FROM website_keywords
+WHERE EXISTS (
+    SELECT 1 FROM website_keywords AS newer
+    WHERE newer.keyword_id = website_keywords.keyword_id
+        AND newer.website_id = website_keywords.website_id
+        AND newer.id > website_keywords.id
+);
+
+ALTER TABLE website_keywords DROP CONSTRAINT IF EXISTS unique_keyword_website;
+ALTER TABLE website_keywords ADD CONSTRAINT unique_keyword_website UNIQUE (keyword_id, website_id);
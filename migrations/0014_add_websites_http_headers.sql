@@ -0,0 +1,2 @@
+-- HTTP response headers of each website as a JSON array of [name, value] pairs, in the order they were sent
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS http_headers JSONB;
@@ -0,0 +1,3 @@
+-- The main content of each website, which its keywords come from, and all of its visible text
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS main_content TEXT;
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS full_text TEXT;
@@ -0,0 +1,6 @@
+-- Metadata of the record each website was read from
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS warc_date TIMESTAMPTZ;
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS identified_payload_type TEXT;
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS status_code INT;
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS content_type TEXT;
+ALTER TABLE websites ADD COLUMN IF NOT EXISTS content_length BIGINT;
@@ -4,9 +4,70 @@ CREATE TABLE websites (
     description TEXT NOT NULL,
     url TEXT UNIQUE NOT NULL,
     word_count INT NOT NULL,
+    lang TEXT,
+    -- Where the description came from: meta, og, twitter or snippet
+    description_source TEXT,
+    -- Set by `cargo run -- pagerank`
+    pagerank DOUBLE PRECISION,
+    -- SimHash of the body and its four 16-bit bands tagged with their index, see simhash.rs
+    simhash BIGINT,
+    simhash_bands INT[],
+    -- The first stored copy of a near-duplicate page
+    duplicate_of INT REFERENCES websites(id) ON DELETE SET NULL,
+    -- WARC-Payload-Digest of the response, shared by byte-identical pages
+    payload_digest TEXT,
+    -- The kind of file the website was stored from: warc, wet or wat
+    input_kind TEXT NOT NULL DEFAULT 'warc',
+    -- HTTP response headers as a JSON array of [name, value] pairs
+    http_headers JSONB,
+    -- The main content the keywords come from, NULL when too little was found, and all visible text
+    main_content TEXT,
+    full_text TEXT,
+    -- Metadata of the record the website was read from
+    warc_date TIMESTAMPTZ,
+    identified_payload_type TEXT,
+    status_code INT,
+    content_type TEXT,
+    content_length BIGINT,
     CONSTRAINT unique_url UNIQUE (url) 
 );
 
+CREATE INDEX idx_websites_lang ON websites (lang);
+CREATE INDEX idx_websites_simhash_bands ON websites USING GIN (simhash_bands);
+CREATE INDEX idx_websites_duplicate_of ON websites (duplicate_of);
+CREATE INDEX idx_websites_payload_digest ON websites (payload_digest);
+
+-- URLs whose payload is identical to a stored website, which are not parsed or stored themselves
+CREATE TABLE website_aliases (
+    id SERIAL PRIMARY KEY,
+    url TEXT UNIQUE NOT NULL,
+    website_id INT NOT NULL REFERENCES websites(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_website_aliases_website_id ON website_aliases (website_id);
+
+-- 3xx responses and where their `Location` header points, used to resolve links to moved pages
+CREATE TABLE redirects (
+    id SERIAL PRIMARY KEY,
+    source_url TEXT UNIQUE NOT NULL,
+    target_url TEXT NOT NULL,
+    status_code INT NOT NULL
+);
+
+-- URLs that answered with a 4xx or 5xx status, only filled when STORE_ERROR_STATUSES=true
+CREATE TABLE url_statuses (
+    id SERIAL PRIMARY KEY,
+    url TEXT UNIQUE NOT NULL,
+    status_code INT NOT NULL
+);
+
+-- Sum of the PageRank of every page on a host
+CREATE TABLE host_pagerank (
+    host TEXT PRIMARY KEY,
+    pagerank DOUBLE PRECISION NOT NULL,
+    page_count INT NOT NULL
+);
+
 CREATE TABLE keywords (
     id SERIAL PRIMARY KEY,
     word TEXT UNIQUE NOT NULL,
@@ -21,7 +82,16 @@ CREATE TABLE website_keywords (
     keyword_id INT NOT NULL REFERENCES keywords(id),
     website_id INT NOT NULL REFERENCES websites(id),
     keyword_occurrences INT NOT NULL,
-    CONSTRAINT unique_keyword_website UNIQUE (keyword_id, website_id, keyword_occurrences) 
+    -- Word positions of the keyword in the body, delta-encoded: the first position, then the gap to each next one
+    positions INT[] NOT NULL DEFAULT '{}',
+    -- Occurrences in the other indexed fields, keyword_occurrences counts the body
+    title_occurrences INT NOT NULL DEFAULT 0,
+    description_occurrences INT NOT NULL DEFAULT 0,
+    heading_occurrences INT NOT NULL DEFAULT 0,
+    url_occurrences INT NOT NULL DEFAULT 0,
+    -- Occurrences in the anchor text of links pointing at the website
+    anchor_occurrences INT NOT NULL DEFAULT 0,
+    CONSTRAINT unique_keyword_website UNIQUE (keyword_id, website_id) 
 );
 
 CREATE INDEX idx_website_keywords_keyword_id ON website_keywords (keyword_id);
@@ -32,8 +102,33 @@ CREATE TABLE website_links (
     id SERIAL PRIMARY KEY,
     source_website_id INT NOT NULL REFERENCES websites(id),
     target_website TEXT NOT NULL,
+    -- Set once the target URL has been indexed
+    target_website_id INT REFERENCES websites(id) ON DELETE SET NULL,
+    anchor_text TEXT,
+    title TEXT,
+    rel TEXT,
+    -- rel="nofollow", "ugc" or "sponsored", or a link on a page marked nofollow
+    nofollow BOOLEAN NOT NULL DEFAULT FALSE,
     CONSTRAINT unique_source_target UNIQUE (source_website_id, target_website) 
 );
 
 CREATE INDEX idx_website_links_source ON website_links (source_website_id);
 CREATE INDEX idx_website_links_target ON website_links (target_website);
+CREATE INDEX idx_website_links_target_id ON website_links (target_website_id);
+
+CREATE TABLE link_keywords (
+    id BIGSERIAL PRIMARY KEY,
+    link_id INT NOT NULL REFERENCES website_links(id) ON DELETE CASCADE,
+    keyword_id INT NOT NULL REFERENCES keywords(id),
+    occurrences INT NOT NULL,
+    CONSTRAINT unique_link_keyword UNIQUE (link_id, keyword_id)
+);
+
+CREATE INDEX idx_link_keywords_link_id ON link_keywords (link_id);
+
+-- Ledger of the records of each file that are already stored, so interrupted files resume without counting a page twice
+CREATE TABLE ingested_records (
+    file_name TEXT NOT NULL,
+    record_id TEXT NOT NULL,
+    PRIMARY KEY (file_name, record_id)
+);
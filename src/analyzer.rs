@@ -0,0 +1,167 @@
+use log::warn;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rust_stemmers::{ Algorithm, Stemmer };
+use std::collections::{ HashMap, HashSet };
+use std::env;
+use stop_words::LANGUAGE;
+use whichlang::{ Lang, LANGUAGES };
+
+use crate::helper_functions::fetch_lines;
+use crate::tokenizer::{ self, Token, TokenizerOptions };
+
+// Languages accepted for indexing, read from `ACCEPTED_LANGUAGES` as comma separated
+// three-letter codes such as `eng,fra,deu`. Only English is accepted by default.
+pub static ACCEPTED_LANGUAGES: Lazy<Vec<Lang>> = Lazy::new(|| {
+    let codes: String = env::var("ACCEPTED_LANGUAGES").unwrap_or_else(|_| "eng".to_string());
+    codes
+        .split(',')
+        .map(|code| code.trim().to_lowercase())
+        .filter(|code| !code.is_empty())
+        .filter_map(|code| {
+            let lang: Option<Lang> = LANGUAGES.iter()
+                .copied()
+                .find(|lang| lang.three_letter_code() == code);
+            if lang.is_none() {
+                warn!("Ignoring unsupported language in ACCEPTED_LANGUAGES: {}", code);
+            }
+            lang
+        })
+        .collect()
+});
+
+pub fn is_accepted_language(lang: Lang) -> bool {
+    ACCEPTED_LANGUAGES.contains(&lang)
+}
+
+// Turns text in one language into the terms stored in the index
+pub struct Analyzer {
+    lang: Lang,
+    stemmer: Option<Stemmer>,
+    stopwords: HashSet<&'static str>,
+    tokenizer_options: TokenizerOptions,
+}
+
+impl Analyzer {
+    fn new(lang: Lang) -> Self {
+        // English keeps the lemma list, other languages use a Snowball stemmer where one exists
+        let stemmer: Option<Stemmer> = match lang {
+            Lang::Ara => Some(Algorithm::Arabic),
+            Lang::Deu => Some(Algorithm::German),
+            Lang::Fra => Some(Algorithm::French),
+            Lang::Ita => Some(Algorithm::Italian),
+            Lang::Nld => Some(Algorithm::Dutch),
+            Lang::Por => Some(Algorithm::Portuguese),
+            Lang::Rus => Some(Algorithm::Russian),
+            Lang::Spa => Some(Algorithm::Spanish),
+            Lang::Swe => Some(Algorithm::Swedish),
+            Lang::Tur => Some(Algorithm::Turkish),
+            Lang::Eng | Lang::Cmn | Lang::Hin | Lang::Jpn | Lang::Kor | Lang::Vie => None,
+        }.map(Stemmer::create);
+
+        Analyzer {
+            lang,
+            stemmer,
+            stopwords: stop_words::get(stopword_language(lang)).iter().copied().collect(),
+            tokenizer_options: TokenizerOptions {
+                fold_diacritics: env::var("FOLD_DIACRITICS").is_ok_and(|value| value == "true"),
+            },
+        }
+    }
+
+    // Tokenize text and normalise each remaining token, keeping its offsets in the text
+    pub fn analyze(&self, text: &str) -> Vec<Token> {
+        tokenizer
+            ::tokenize(text, &self.tokenizer_options)
+            .into_iter()
+            .filter(|token| !self.stopwords.contains(token.text.as_str()))
+            .map(|token| Token {
+                text: self.normalise(&token.text),
+                ..token
+            })
+            .collect()
+    }
+
+    fn normalise(&self, word: &str) -> String {
+        if self.lang == Lang::Eng {
+            return LEMMA_MAP.get(word).cloned().unwrap_or_else(|| word.to_string());
+        }
+        match &self.stemmer {
+            Some(stemmer) => stemmer.stem(word).into_owned(),
+            None => word.to_string(),
+        }
+    }
+}
+
+pub fn analyzer_for(lang: Lang) -> &'static Analyzer {
+    ANALYZERS.iter()
+        .find(|analyzer| analyzer.lang == lang)
+        .expect("Every detectable language has an analyzer")
+}
+
+static ANALYZERS: Lazy<Vec<Analyzer>> = Lazy::new(|| {
+    LANGUAGES.iter().copied().map(Analyzer::new).collect()
+});
+
+fn stopword_language(lang: Lang) -> LANGUAGE {
+    match lang {
+        Lang::Ara => LANGUAGE::Arabic,
+        Lang::Cmn => LANGUAGE::Chinese,
+        Lang::Deu => LANGUAGE::German,
+        Lang::Eng => LANGUAGE::English,
+        Lang::Fra => LANGUAGE::French,
+        Lang::Hin => LANGUAGE::Hindi,
+        Lang::Ita => LANGUAGE::Italian,
+        Lang::Jpn => LANGUAGE::Japanese,
+        Lang::Kor => LANGUAGE::Korean,
+        Lang::Nld => LANGUAGE::Dutch,
+        Lang::Por => LANGUAGE::Portuguese,
+        Lang::Rus => LANGUAGE::Russian,
+        Lang::Spa => LANGUAGE::Spanish,
+        Lang::Swe => LANGUAGE::Swedish,
+        Lang::Tur => LANGUAGE::Turkish,
+        Lang::Vie => LANGUAGE::Vietnamese,
+    }
+}
+
+// Global static for storing the English lemma mappings.
+static LEMMA_MAP: Lazy<HashMap<String, String>> = Lazy::new(|| {
+    let mut map = HashMap::new();
+    let lines = fetch_lines(0, "lemmatised_words.txt").expect("Failed to read lemma file");
+
+    let re = Regex::new(r"^([^/]+)[^->]*->(.+)$").unwrap();
+    for entry in lines {
+        if let Some(captures) = re.captures(&entry) {
+            let lemma = captures[1].trim().to_string();
+            let words: Vec<&str> = captures[2]
+                .split(',')
+                .map(|word| word.trim())
+                .collect();
+            for word in words {
+                map.insert(word.to_string(), lemma.clone());
+            }
+        }
+    }
+    map
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_stopwords_but_keeps_their_positions() {
+        let tokens: Vec<Token> = analyzer_for(Lang::Eng).analyze("The cats are on the mat");
+        let terms: Vec<(&str, usize)> = tokens.iter().map(|token| (token.text.as_str(), token.position)).collect();
+
+        assert_eq!(terms, [("cat", 1), ("mat", 5)]);
+    }
+
+    #[test]
+    fn stems_languages_without_a_lemma_list() {
+        let tokens: Vec<Token> = analyzer_for(Lang::Fra).analyze("Les chats et les chiens");
+        let terms: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+
+        assert_eq!(terms, ["chat", "chien"]);
+    }
+}
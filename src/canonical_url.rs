@@ -0,0 +1,90 @@
+use once_cell::sync::Lazy;
+use std::env;
+use url::Url;
+
+// Query parameters that only track where a visitor came from. A trailing `*` matches any suffix.
+const DEFAULT_TRACKING_PARAMETERS: &str =
+    "utm_*,gclid,dclid,fbclid,msclkid,yclid,mc_cid,mc_eid,_ga,_gl,igshid,ref_src";
+
+// Read from `TRACKING_PARAMETERS` as a comma separated list, replacing the defaults
+static TRACKING_PARAMETERS: Lazy<Vec<String>> = Lazy::new(|| {
+    env::var("TRACKING_PARAMETERS")
+        .unwrap_or_else(|_| DEFAULT_TRACKING_PARAMETERS.to_string())
+        .split(',')
+        .map(|parameter| parameter.trim().to_lowercase())
+        .filter(|parameter| !parameter.is_empty())
+        .collect()
+});
+
+// Whether http URLs are stored as https, on by default and turned off with `CANONICAL_HTTPS=false`
+static FORCE_HTTPS: Lazy<bool> = Lazy::new(|| {
+    env::var("CANONICAL_HTTPS").map_or(true, |value| value != "false")
+});
+
+// Normalise a URL so that variants of the same page map to one string.
+// The scheme and host are lowercased, http becomes https, default ports, fragments and tracking
+// parameters are dropped, the remaining parameters are sorted and trailing slashes are removed.
+// Paths are case sensitive on most servers, so their case is kept. Only http(s) URLs are changed.
+pub fn canonicalise(url: &Url) -> Url {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return url.clone();
+    }
+    let mut url: Url = url.clone();
+
+    if *FORCE_HTTPS && url.scheme() == "http" {
+        url.set_scheme("https").ok();
+    }
+    if matches!(url.port(), Some(80) | Some(443)) {
+        url.set_port(None).ok();
+    }
+    url.set_fragment(None);
+
+    let mut parameters: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_parameter(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if parameters.is_empty() {
+        url.set_query(None);
+    } else {
+        parameters.sort();
+        url.query_pairs_mut().clear().extend_pairs(parameters);
+    }
+
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path: String = url.path().trim_end_matches('/').to_string();
+        url.set_path(if path.is_empty() { "/" } else { &path });
+    }
+
+    url
+}
+
+// The URL a page is stored under: its `<link rel="canonical">` when that points at the same host,
+// or else its own URL. Other hosts are ignored so a page can't take over another site's row.
+pub fn page_url(target: &Url, canonical_link: Option<Url>) -> String {
+    let url: &Url = match &canonical_link {
+        Some(link) if
+            matches!(link.scheme(), "http" | "https") &&
+            link.host_str() == target.host_str()
+        => link,
+        _ => target,
+    };
+    canonicalise(url).to_string()
+}
+
+// Canonicalise a URL string, returning None if it can't be parsed
+pub fn canonicalise_str(url: &str) -> Option<String> {
+    Url::parse(url)
+        .ok()
+        .map(|url| canonicalise(&url).to_string())
+}
+
+fn is_tracking_parameter(name: &str) -> bool {
+    let name: String = name.to_lowercase();
+    TRACKING_PARAMETERS.iter().any(|parameter| {
+        match parameter.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == *parameter,
+        }
+    })
+}
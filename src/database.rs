@@ -1,33 +1,109 @@
-use dotenv::dotenv;
+use chrono::DateTime;
 use futures::TryStreamExt;
-use sqlx::{ PgPool, Row, Postgres, Pool, Executor };
+use sqlx::{ ConnectOptions, PgConnection, PgPool, Row, Executor };
+use sqlx::postgres::{ PgConnectOptions, PgPoolOptions };
+use log::LevelFilter;
 use rand::{ thread_rng, Rng };
-use std::collections::{ HashMap, HashSet };
+use std::collections::{ BTreeMap, HashMap, HashSet };
 use std::{ env, path::Path };
 use std::error::Error;
 use std::time::{ Duration, Instant };
 use std::sync::Arc;
 use indicatif::{ MultiProgress, ProgressBar, ProgressStyle };
 use colored::*;
+use tokio::sync::mpsc::Receiver;
 
-use crate::handle_warc::webpage::Webpage;
-use crate::helper_functions;
+use crate::handle_warc::{ AliasOf, InputKind, Redirect, WebpageBatch, WebsiteAlias };
+use crate::handle_warc::webpage::{ FieldTerms, Webpage };
+use crate::helper_functions::{ self, Link };
+use crate::simhash::{ self, NearDuplicateMode, NEAR_DUPLICATE_MODE };
+use crate::tokenizer::Token;
+
+pub mod bulk_load;
+
+use bulk_load::{ InsertMode, INSERT_MODE };
 
 const MAX_KEYWORD_LENGTH: usize = 40;
 
+// Rows of `website_keywords` counted in `keywords.documents_containing_word`, which are those
+// where the keyword appears on the page itself rather than only in links pointing at it
+const COUNTS_AS_DOCUMENT: &str =
+    "(keyword_occurrences > 0 OR title_occurrences > 0 OR description_occurrences > 0 OR heading_occurrences > 0 OR url_occurrences > 0)";
+
+// Most bind parameters Postgres accepts in one statement
+const MAX_BIND_PARAMETERS: usize = u16::MAX as usize;
+
+const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+const DEFAULT_ACQUIRE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 600;
+const DEFAULT_STATEMENT_CACHE_CAPACITY: usize = 100;
+
+// The connection pool shared by every task, created once in `main`
+#[derive(Clone)]
+pub struct Database {
+    pool: PgPool,
+}
+
+impl Database {
+    // Connect to `DATABASE_URL`. The pool is sized and tuned with `DATABASE_MAX_CONNECTIONS`,
+    // `DATABASE_ACQUIRE_TIMEOUT_SECS`, `DATABASE_IDLE_TIMEOUT_SECS` and
+    // `DATABASE_STATEMENT_CACHE_CAPACITY`.
+    pub async fn connect() -> Result<Self, DatabaseError> {
+        let database_url: String = env::var("DATABASE_URL").map_err(|_| DatabaseError::MissingUrl)?;
+        let connect_options: PgConnectOptions = database_url
+            .parse::<PgConnectOptions>()
+            .map_err(DatabaseError::InvalidUrl)?
+            .statement_cache_capacity(
+                env_or("DATABASE_STATEMENT_CACHE_CAPACITY", DEFAULT_STATEMENT_CACHE_CAPACITY)
+            )
+            // Every statement is logged at debug level otherwise
+            .log_statements(LevelFilter::Trace);
+
+        let pool: PgPool = PgPoolOptions::new()
+            .max_connections(env_or("DATABASE_MAX_CONNECTIONS", DEFAULT_MAX_CONNECTIONS))
+            .acquire_timeout(
+                Duration::from_secs(env_or("DATABASE_ACQUIRE_TIMEOUT_SECS", DEFAULT_ACQUIRE_TIMEOUT_SECS))
+            )
+            .idle_timeout(
+                Duration::from_secs(env_or("DATABASE_IDLE_TIMEOUT_SECS", DEFAULT_IDLE_TIMEOUT_SECS))
+            )
+            .after_connect(|connection, _| Box::pin(bulk_load::create_staging_tables(connection)))
+            .connect_with(connect_options).await
+            .map_err(DatabaseError::Connect)?;
+
+        Ok(Database { pool })
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Write batches of webpages to the database as they arrive from the WARC reader
 pub async fn add_webpages(
-    webpages: &Vec<Webpage>,
+    database: &Database,
+    file_name: &str,
+    kind: InputKind,
+    mut receiver: Receiver<WebpageBatch>,
     multibar: &Arc<MultiProgress>,
     file_path: &Path
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let file_number: String = helper_functions::file_path_to_number(file_path);
-    let progress_bar: ProgressBar = multibar.add(ProgressBar::new(webpages.len() as u64));
+    // The total is unknown up front, so the length grows with every batch received
+    let progress_bar: ProgressBar = multibar.add(ProgressBar::new(0));
     progress_bar.set_style(
         ProgressStyle::default_bar()
             .template(
                 &format!(
-                    "{}: [{{elapsed_precise}}] [{{wide_bar:40.cyan/blue}}] Added to db: {{pos}}/{{len}} | Time Left: {{eta}} | {{msg}}",
-                    format!("Adding {}", file_number.green().bold())
+                    "Adding {}: [{{elapsed_precise}}] [{{wide_bar:40.cyan/blue}}] Added to db: {{pos}}/{{len}} received | {{msg}}",
+                    file_number.green().bold()
                 )
             )
             .unwrap()
@@ -35,97 +111,176 @@ pub async fn add_webpages(
     );
     progress_bar.tick();
 
-    dotenv().ok();
     let duration: Instant = std::time::Instant::now();
-    let database_url: String = env
-        ::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set in the .env file");
-    let pool: Pool<Postgres> = PgPool::connect(&database_url).await.expect(
-        "Failed to connect to the database"
+    let pool: &PgPool = database.pool();
+
+    let mut added_count: usize = 0;
+    while let Some(batch) = receiver.recv().await {
+        progress_bar.inc_length(batch.webpages.len() as u64);
+        added_count += add_webpage_batch(file_name, kind, &batch.webpages, pool, &progress_bar).await?;
+        // Aliases go in after the batch, as their page may be part of it
+        add_website_aliases(&batch.aliases, pool).await?;
+        add_redirects(&batch.redirects, pool).await?;
+        add_error_statuses(&batch.error_statuses, pool).await?;
+    }
+
+    let msg: String = format!(
+        "{} | {} | {}",
+        format!("Added {} to database", file_number).green().bold(),
+        format!("Time taken overall: {:.2}s", duration.elapsed().as_secs_f64()).cyan(),
+        format!("Number of webpages added: {}", added_count).yellow()
     );
+    progress_bar.println(msg);
+    progress_bar.finish_and_clear();
+    Ok(())
+}
 
+// Write each page with the given insert mode in its own transaction, which is rolled back,
+// returning how many pages were written and the total time spent writing them
+pub async fn time_insert_mode(
+    database: &Database,
+    webpages: &[Webpage],
+    kind: InputKind,
+    mode: InsertMode
+) -> Result<(usize, Duration), Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
     let filtered_webpages: Vec<&Webpage> = webpages
         .iter()
-        .filter(|wp| wp.title.is_some() && wp.description.is_some() && wp.warc_target_uri.is_some())
+        .filter(|wp| wp.title.is_some() && wp.canonical_url.is_some())
         .collect();
 
-    // Collect all keywords from all webpages
-    let mut keyword_counts: HashMap<String, HashSet<String>> = HashMap::new();
+    // Rolling back every page keeps the database as it was, so each mode sees the same tables
+    let mut elapsed: Duration = Duration::ZERO;
     for wp in &filtered_webpages {
-        if let Some(url) = &wp.warc_target_uri {
-            let keywords: Vec<String> = wp.lemmatised_text.clone().unwrap_or_default();
-            let truncated_keywords: Vec<String> = keywords
-                .iter()
-                .map(|keyword| {
-                    if keyword.len() > MAX_KEYWORD_LENGTH {
-                        keyword[..MAX_KEYWORD_LENGTH].to_owned()
-                    } else {
-                        keyword.clone()
-                    }
-                })
-                .collect();
+        let mut transaction = pool.begin().await?;
+        let start: Instant = Instant::now();
+        write_webpage("bench-ingest", kind, wp, mode, &mut transaction).await?;
+        elapsed += start.elapsed();
+        transaction.rollback().await?;
+    }
 
-            for keyword in truncated_keywords {
-                keyword_counts.entry(keyword).or_insert_with(HashSet::new).insert(url.clone());
+    Ok((filtered_webpages.len(), elapsed))
+}
+
+// The WARC-Payload-Digests among these that belong to a page already stored
+pub async fn stored_payload_digests(
+    database: &Database,
+    payload_digests: &[String]
+) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+    let query: &str = "SELECT DISTINCT payload_digest FROM websites WHERE payload_digest = ANY($1)";
+    let digests: Vec<String> = sqlx::query_scalar(query).bind(payload_digests).fetch_all(pool).await?;
+    Ok(digests.into_iter().collect())
+}
+
+// Record URLs whose content is the same as a stored page. Aliases of pages that weren't stored,
+// and aliases that are the stored page's own URL, are ignored.
+async fn add_website_aliases(
+    aliases: &[WebsiteAlias],
+    pool: &PgPool
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Alias URLs and their originals, split by how the original is identified
+    let mut by_digest: (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+    let mut by_url: (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+    for alias in aliases {
+        match &alias.original {
+            AliasOf::PayloadDigest(digest) => {
+                by_digest.0.push(alias.url.clone());
+                by_digest.1.push(digest.clone());
+            }
+            AliasOf::Url(url) => {
+                by_url.0.push(alias.url.clone());
+                by_url.1.push(url.clone());
             }
         }
     }
 
-    // Batch insert keywords and get their ids
-    let mut keyword_id_map: HashMap<String, i32> = HashMap::new();
-    if !keyword_counts.is_empty() {
-        let insert_keywords_query = format!(
-            "INSERT INTO keywords (word, documents_containing_word) VALUES {} ON CONFLICT (word) DO UPDATE SET documents_containing_word = keywords.documents_containing_word + EXCLUDED.documents_containing_word RETURNING id, word",
-            keyword_counts
-                .keys()
-                .enumerate()
-                .map(|(i, _)| format!("(${}, ${})", i * 2 + 1, i * 2 + 2))
-                .collect::<Vec<_>>()
-                .join(", ")
+    for (column, (urls, originals)) in [("payload_digest", by_digest), ("url", by_url)] {
+        if urls.is_empty() {
+            continue;
+        }
+        let query: String = format!(
+            r#"
+    INSERT INTO website_aliases (url, website_id)
+    SELECT DISTINCT ON (aliases.url) aliases.url, websites.id
+    FROM UNNEST($1::TEXT[], $2::TEXT[]) AS aliases(url, original)
+    JOIN websites ON websites.{} = aliases.original
+    WHERE websites.url <> aliases.url
+    ORDER BY aliases.url, websites.id
+    ON CONFLICT (url) DO UPDATE SET website_id = EXCLUDED.website_id
+    "#,
+            column
         );
+        sqlx::query(&query).bind(urls).bind(originals).execute(pool).await?;
+    }
+    Ok(())
+}
 
-        let backoff_delay = Duration::from_millis(thread_rng().gen_range(100..500)); // Random initial delay between 100ms to 500ms
-        let mut attempt: i32 = 0;
+// Record where redirected URLs lead, keeping the latest target of each
+async fn add_redirects(
+    redirects: &[Redirect],
+    pool: &PgPool
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if redirects.is_empty() {
+        return Ok(());
+    }
 
-        loop {
-            let mut transaction = pool.begin().await?;
-            let mut query = sqlx::query(&insert_keywords_query);
+    let query: &str =
+        r#"
+    INSERT INTO redirects (source_url, target_url, status_code)
+    SELECT DISTINCT ON (source_url) source_url, target_url, status_code
+    FROM UNNEST($1::TEXT[], $2::TEXT[], $3::INT[]) AS redirects(source_url, target_url, status_code)
+    ON CONFLICT (source_url) DO UPDATE
+        SET target_url = EXCLUDED.target_url, status_code = EXCLUDED.status_code
+    "#;
+    sqlx::query(query)
+        .bind(redirects.iter().map(|redirect| redirect.source_url.clone()).collect::<Vec<_>>())
+        .bind(redirects.iter().map(|redirect| redirect.target_url.clone()).collect::<Vec<_>>())
+        .bind(redirects.iter().map(|redirect| redirect.status_code).collect::<Vec<_>>())
+        .execute(pool).await?;
+    Ok(())
+}
 
-            for (keyword, urls) in &keyword_counts {
-                query = query.bind(keyword).bind(urls.len() as i32);
-            }
+// Record the status of URLs that answered with an error, for checking link health
+async fn add_error_statuses(
+    statuses: &[(String, i32)],
+    pool: &PgPool
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if statuses.is_empty() {
+        return Ok(());
+    }
 
-            let result = query.fetch_all(&mut *transaction).await;
+    let query: &str =
+        r#"
+    INSERT INTO url_statuses (url, status_code)
+    SELECT DISTINCT ON (url) url, status_code
+    FROM UNNEST($1::TEXT[], $2::INT[]) AS statuses(url, status_code)
+    ON CONFLICT (url) DO UPDATE SET status_code = EXCLUDED.status_code
+    "#;
+    let (urls, status_codes): (Vec<String>, Vec<i32>) = statuses.iter().cloned().unzip();
+    sqlx::query(query).bind(urls).bind(status_codes).execute(pool).await?;
+    Ok(())
+}
 
-            match result {
-                Ok(rows) => {
-                    transaction.commit().await?;
-                    for row in rows {
-                        let keyword_id: i32 = row.get(0);
-                        let keyword_word: String = row.get(1);
-                        keyword_id_map.insert(keyword_word, keyword_id);
-                    }
-                    break; // Break out of the retry loop on success
-                }
-                Err(err) => {
-                    transaction.rollback().await?; // Rollback on error
-                    if let Some(db_error) = err.as_database_error() {
-                        if db_error.code().unwrap_or_default() == "40P01" {
-                            attempt += 1;
-                            let sleep_duration = backoff_delay * (attempt as u32);
-                            tokio::time::sleep(sleep_duration).await;
-                            continue;
-                        }
-                    }
-                    return Err(Box::new(err));
-                }
-            }
-        }
-    }
+// Add a single batch of webpages, returning how many were written
+async fn add_webpage_batch(
+    file_name: &str,
+    kind: InputKind,
+    webpages: &[Webpage],
+    pool: &PgPool,
+    progress_bar: &ProgressBar
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let filtered_webpages: Vec<&Webpage> = webpages
+        .iter()
+        .filter(|wp| wp.title.is_some() && wp.canonical_url.is_some())
+        .collect();
 
-    for wp in filtered_webpages.iter() {
+    let mut added_count: usize = 0;
+    for wp in &filtered_webpages {
         let time_for_webpage: Instant = std::time::Instant::now();
-        add_webpage(&wp, &pool, &keyword_id_map).await?;
+        if ingest_webpage(file_name, kind, wp, pool).await? {
+            added_count += 1;
+        }
         let time_taken: f64 = time_for_webpage.elapsed().as_secs_f64();
         let msg: String = format!("Time taken for last webpage: {:.2}s", time_taken)
             .cyan()
@@ -133,51 +288,252 @@ pub async fn add_webpages(
         progress_bar.set_message(msg);
         progress_bar.inc(1);
     }
+    // Skipped webpages still count towards the received total
+    progress_bar.inc((webpages.len() - filtered_webpages.len()) as u64);
 
-    let msg: String = format!(
-        "{} | {} | {}",
-        format!("Added {} to database", file_number).green().bold(),
-        format!("Time taken overall: {:.2}s", duration.elapsed().as_secs_f64()).cyan(),
-        format!("Number of webpages added: {}", filtered_webpages.len()).yellow()
-    );
-    progress_bar.println(msg);
-    progress_bar.finish_and_clear();
-    Ok(())
+    Ok(added_count)
+}
+
+// Write a webpage, its keyword counts and its ledger entry in one transaction, retrying on deadlock.
+// Either all of it is stored or none of it, so an interrupted file can be resumed from its ledger.
+// Returns false for near-duplicates skipped by `NEAR_DUPLICATE_MODE` and pages that would replace a better copy.
+async fn ingest_webpage(
+    file_name: &str,
+    kind: InputKind,
+    webpage: &Webpage,
+    pool: &PgPool
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let backoff_delay = Duration::from_millis(thread_rng().gen_range(100..500)); // Random initial delay between 100ms to 500ms
+    let mut attempt: i32 = 0;
+
+    loop {
+        let mut transaction = pool.begin().await?;
+        let result = write_webpage(file_name, kind, webpage, *INSERT_MODE, &mut transaction).await;
+
+        match result {
+            Ok(stored) => {
+                transaction.commit().await?;
+                return Ok(stored);
+            }
+            Err(err) => {
+                transaction.rollback().await?; // Rollback on error
+                let is_deadlock: bool = err
+                    .downcast_ref::<sqlx::Error>()
+                    .and_then(|err| err.as_database_error())
+                    .is_some_and(|db_error| db_error.code().unwrap_or_default() == "40P01");
+                if is_deadlock {
+                    attempt += 1;
+                    let sleep_duration = backoff_delay * (attempt as u32);
+                    tokio::time::sleep(sleep_duration).await;
+                    continue;
+                }
+                return Err(err);
+            }
+        }
+    }
+}
+
+async fn write_webpage(
+    file_name: &str,
+    kind: InputKind,
+    webpage: &Webpage,
+    mode: InsertMode,
+    connection: &mut PgConnection
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // Find the first stored copy of a near-duplicate page
+    let duplicate_of: Option<i32> = find_near_duplicate(webpage, &mut *connection).await?;
+    let stored: bool =
+        (duplicate_of.is_none() || *NEAR_DUPLICATE_MODE != NearDuplicateMode::Skip) &&
+        !has_better_copy(webpage, kind, &mut *connection).await?;
+
+    if stored {
+        let keyword_id_map: HashMap<String, i32> = upsert_keywords(
+            webpage,
+            duplicate_of,
+            mode,
+            &mut *connection
+        ).await?;
+        add_webpage(webpage, kind, duplicate_of, mode, &mut *connection, &keyword_id_map).await?;
+    }
+
+    // Every record has an ID, but a page without one is simply not resumable
+    if let Some(record_id) = &webpage.record_id {
+        let ledger_query: &str =
+            r#"
+    INSERT INTO ingested_records (file_name, record_id) VALUES ($1, $2)
+    ON CONFLICT (file_name, record_id) DO NOTHING
+    "#;
+        sqlx::query(ledger_query).bind(file_name).bind(record_id).execute(&mut *connection).await?;
+    }
+
+    Ok(stored)
+}
+
+// Whether the page is already stored from a richer kind of file, which it shouldn't replace.
+// WET text doesn't replace pages parsed from WARC responses, and WAT pages have no body,
+// so they leave pages stored from either of the other kinds alone.
+async fn has_better_copy(
+    webpage: &Webpage,
+    kind: InputKind,
+    connection: &mut PgConnection
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    let richer_kinds: Vec<&str> = match kind {
+        InputKind::Warc => {
+            return Ok(false);
+        }
+        InputKind::Wet => vec![InputKind::Warc.as_str()],
+        InputKind::Wat => vec![InputKind::Warc.as_str(), InputKind::Wet.as_str()],
+    };
+    let better_copy_query: &str = "SELECT EXISTS (SELECT 1 FROM websites WHERE url = $1 AND input_kind = ANY($2))";
+    Ok(
+        sqlx
+            ::query_scalar(better_copy_query)
+            .bind(&webpage.canonical_url)
+            .bind(richer_kinds)
+            .fetch_one(&mut *connection).await?
+    )
+}
+
+// Count the webpage in the document frequency of each of its keywords and return their ids.
+// Near-duplicates don't have their own keywords, and anchor text belongs to the linked page,
+// so neither adds to document counts here.
+async fn upsert_keywords(
+    webpage: &Webpage,
+    duplicate_of: Option<i32>,
+    mode: InsertMode,
+    connection: &mut PgConnection
+) -> Result<HashMap<String, i32>, Box<dyn Error + Send + Sync>> {
+    // Keywords are locked in the same order by every writer, which keeps deadlocks rare
+    let mut keyword_counts: BTreeMap<String, i32> = BTreeMap::new();
+    if duplicate_of.is_none() {
+        for keyword in keyword_stats(webpage).into_keys() {
+            keyword_counts.insert(keyword, 1);
+        }
+    }
+    for link in webpage.links.iter().flatten() {
+        for term in &link.anchor_terms {
+            keyword_counts.entry(truncate_keyword(term)).or_insert(0);
+        }
+    }
+
+    let mut keyword_id_map: HashMap<String, i32> = HashMap::new();
+    if keyword_counts.is_empty() {
+        return Ok(keyword_id_map);
+    }
+    if mode == InsertMode::Copy {
+        return bulk_load::copy_keywords(&mut *connection, &keyword_counts).await;
+    }
+
+    // Chunks are written in word order too
+    let keyword_counts: Vec<(String, i32)> = keyword_counts.into_iter().collect();
+    for chunk in keyword_counts.chunks(rows_per_statement(2)) {
+        let mut query_builder = sqlx::QueryBuilder::new("INSERT INTO keywords (word, documents_containing_word) ");
+        query_builder.push_values(chunk, |mut b, (keyword, count)| {
+            b.push_bind(keyword).push_bind(count);
+        });
+        query_builder.push(
+            " ON CONFLICT (word) DO UPDATE SET documents_containing_word = keywords.documents_containing_word + EXCLUDED.documents_containing_word RETURNING id, word"
+        );
+
+        for row in query_builder.build().fetch_all(&mut *connection).await? {
+            let keyword_id: i32 = row.get(0);
+            let keyword_word: String = row.get(1);
+            keyword_id_map.insert(keyword_word, keyword_id);
+        }
+    }
+    Ok(keyword_id_map)
+}
+
+// Find a stored page whose fingerprint is within `simhash::MAX_DISTANCE` bits of this one.
+// Candidates share at least one band, and pages that are duplicates themselves are never chosen.
+async fn find_near_duplicate(
+    webpage: &Webpage,
+    connection: &mut PgConnection
+) -> Result<Option<i32>, Box<dyn Error + Send + Sync>> {
+    let fingerprint: u64 = match webpage.simhash {
+        Some(fingerprint) if *NEAR_DUPLICATE_MODE != NearDuplicateMode::Off => fingerprint,
+        _ => {
+            return Ok(None);
+        }
+    };
+
+    let candidates_query: &str =
+        r#"
+    SELECT id, simhash FROM websites
+    WHERE simhash_bands && $1 AND url <> $2 AND duplicate_of IS NULL
+    ORDER BY id
+    "#;
+    let candidates: Vec<(i32, i64)> = sqlx
+        ::query_as(candidates_query)
+        .bind(simhash::bands(fingerprint))
+        .bind(&webpage.canonical_url)
+        .fetch_all(&mut *connection).await?;
+
+    Ok(
+        candidates
+            .into_iter()
+            .map(|(id, candidate)| (simhash::hamming_distance(fingerprint, candidate as u64), id))
+            .filter(|(distance, _)| *distance <= simhash::MAX_DISTANCE)
+            .min()
+            .map(|(_, id)| id)
+    )
 }
 
 pub async fn add_webpage(
     webpage: &Webpage,
-    pool: &PgPool,
+    kind: InputKind,
+    duplicate_of: Option<i32>,
+    mode: InsertMode,
+    connection: &mut PgConnection,
     keyword_id_map: &HashMap<String, i32>
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let title: String = webpage.title.clone().unwrap_or_default();
     let description: String = webpage.description.clone().unwrap_or_default();
-    let url: String = webpage.warc_target_uri.clone().unwrap_or_default();
-    let word_count: i32 = webpage.lemmatised_text.clone().unwrap_or_default().len() as i32;
-    let keywords: Vec<String> = webpage.lemmatised_text.clone().unwrap_or_default();
-    let links: Vec<String> = webpage.links.clone().unwrap_or_default();
-
-    // Truncate keywords to maximum length
-    let truncated_keywords: Vec<String> = keywords
-        .iter()
-        .map(|keyword| (
-            if keyword.len() > MAX_KEYWORD_LENGTH {
-                keyword[..MAX_KEYWORD_LENGTH].to_owned()
-            } else {
-                keyword.clone()
-            }
-        ))
-        .collect();
+    let url: String = webpage.canonical_url.clone().unwrap_or_default();
+    let word_count: i32 = webpage.lemmatised_text.as_ref().map_or(0, |tokens| tokens.len()) as i32;
+    let links: Vec<Link> = webpage.links.clone().unwrap_or_default();
+    let lang: Option<&str> = webpage.lang.map(|lang| lang.three_letter_code());
+    let description_source: Option<&str> = webpage.description_source.map(|source| source.as_str());
+    // Fingerprints are stored as signed integers, keeping their bits
+    let simhash: Option<i64> = webpage.simhash.map(|fingerprint| fingerprint as i64);
+    let simhash_bands: Option<Vec<i32>> = webpage.simhash.map(simhash::bands);
+    let payload_digest: Option<&str> = webpage.payload_digest.as_deref();
+    let http_headers: Option<String> = webpage.http_headers
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?;
+    // Dates that aren't valid RFC 3339 are left out rather than failing the insert
+    let warc_date: Option<String> = webpage.warc_date
+        .as_deref()
+        .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map(|date| date.to_rfc3339());
+    let status_code: Option<i32> = webpage.status_code.map(|status_code| status_code as i32);
+    let content_length: Option<i64> = webpage.content_length.map(|length| length as i64);
 
     // Upsert websites
     let upsert_website_query: &str =
         r#"
-    INSERT INTO websites (title, description, url, word_count)
-    VALUES ($1, $2, $3, $4)
+    INSERT INTO websites (title, description, url, word_count, lang, description_source, simhash, simhash_bands, duplicate_of, payload_digest, input_kind, http_headers, main_content, full_text, warc_date, identified_payload_type, status_code, content_type, content_length)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12::JSONB, $13, $14, $15::TIMESTAMPTZ, $16, $17, $18, $19)
     ON CONFLICT (url) DO UPDATE 
         SET title = EXCLUDED.title, 
             description = EXCLUDED.description, 
-            word_count = EXCLUDED.word_count
+            word_count = EXCLUDED.word_count,
+            lang = EXCLUDED.lang,
+            description_source = EXCLUDED.description_source,
+            simhash = EXCLUDED.simhash,
+            simhash_bands = EXCLUDED.simhash_bands,
+            duplicate_of = EXCLUDED.duplicate_of,
+            payload_digest = EXCLUDED.payload_digest,
+            input_kind = EXCLUDED.input_kind,
+            http_headers = EXCLUDED.http_headers,
+            main_content = EXCLUDED.main_content,
+            full_text = EXCLUDED.full_text,
+            warc_date = EXCLUDED.warc_date,
+            identified_payload_type = EXCLUDED.identified_payload_type,
+            status_code = EXCLUDED.status_code,
+            content_type = EXCLUDED.content_type,
+            content_length = EXCLUDED.content_length
     RETURNING id, url
     "#;
 
@@ -186,139 +542,415 @@ pub async fn add_webpage(
         .bind(&title)
         .bind(&description)
         .bind(&url)
-        .bind(&word_count)
-        .fetch_one(pool).await?;
+        .bind(word_count)
+        .bind(lang)
+        .bind(description_source)
+        .bind(simhash)
+        .bind(simhash_bands)
+        .bind(duplicate_of)
+        .bind(payload_digest)
+        .bind(kind.as_str())
+        .bind(http_headers)
+        .bind(&webpage.text_body)
+        .bind(&webpage.full_text)
+        .bind(warc_date)
+        .bind(&webpage.warc_identified_payload_type)
+        .bind(status_code)
+        .bind(&webpage.content_type)
+        .bind(content_length)
+        .fetch_one(&mut *connection).await?;
 
     let website_id: i32 = row.get(0);
 
-    // Delete existing keywords and links for this website
-    let delete_keywords_query: &str =
+    // Remember which pages the old links pointed at, their anchor text credit changes too
+    let old_targets_query: &str =
         r#"
-    DELETE FROM website_keywords WHERE website_id = $1
+    SELECT DISTINCT target_website_id FROM website_links
+    WHERE source_website_id = $1 AND target_website_id IS NOT NULL
     "#;
-    sqlx::query(delete_keywords_query).bind(&website_id).execute(pool).await?;
+    let old_target_ids: Vec<i32> = sqlx
+        ::query_scalar(old_targets_query)
+        .bind(website_id)
+        .fetch_all(&mut *connection).await?;
+
+    // Delete existing keywords and links for this website, its new keywords were counted already
+    remove_website_keywords(&mut *connection, &[website_id]).await?;
 
     let delete_links_query: &str =
         r#"
     DELETE FROM website_links WHERE source_website_id = $1
     "#;
-    sqlx::query(delete_links_query).bind(&website_id).execute(pool).await?;
+    sqlx::query(delete_links_query).bind(website_id).execute(&mut *connection).await?;
 
-    // Prepare data for bulk insert of website_keywords
-    let mut website_keywords_values: Vec<(i32, i32, i32)> = Vec::new();
-    let mut keyword_counts: HashMap<String, i32> = HashMap::new();
-    for keyword in truncated_keywords {
-        *keyword_counts.entry(keyword).or_insert(0) += 1;
-    }
+    // Prepare data for bulk insert of website_keywords, near-duplicates are found through their copy
+    let website_keywords_values: Vec<(i32, KeywordStats)> = keyword_stats(webpage)
+        .into_iter()
+        .filter(|_| duplicate_of.is_none())
+        .filter_map(|(keyword, stats)| keyword_id_map.get(&keyword).map(|&keyword_id| (keyword_id, stats)))
+        .collect();
 
-    for (keyword, count) in keyword_counts.iter() {
-        if let Some(&keyword_id) = keyword_id_map.get(keyword) {
-            website_keywords_values.push((keyword_id, website_id, *count));
+    if !website_keywords_values.is_empty() {
+        match mode {
+            InsertMode::Copy => {
+                bulk_load::copy_website_keywords(&mut *connection, website_id, &website_keywords_values).await?;
+            }
+            InsertMode::Values => {
+                for chunk in website_keywords_values.chunks(rows_per_statement(8)) {
+                    let mut query_builder = sqlx::QueryBuilder::new(
+                        "INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences, positions, title_occurrences, description_occurrences, heading_occurrences, url_occurrences) "
+                    );
+                    query_builder.push_values(chunk, |mut b, (keyword_id, stats)| {
+                        b.push_bind(keyword_id)
+                            .push_bind(website_id)
+                            .push_bind(stats.body_positions.len() as i32)
+                            .push_bind(delta_encode(&stats.body_positions))
+                            .push_bind(stats.title_occurrences)
+                            .push_bind(stats.description_occurrences)
+                            .push_bind(stats.heading_occurrences)
+                            .push_bind(stats.url_occurrences);
+                    });
+                    query_builder.build().execute(&mut *connection).await?;
+                }
+            }
         }
     }
 
-    if !website_keywords_values.is_empty() {
-        let insert_website_keywords_query = format!(
-            "INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences) VALUES {}",
-            website_keywords_values
-                .iter()
-                .enumerate()
-                .map(|(i, _)| format!("(${}, ${}, ${})", i * 3 + 1, i * 3 + 2, i * 3 + 3))
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
-
-        let mut query = sqlx::query(&insert_website_keywords_query);
-        for (keyword_id, website_id, keyword_occurrences) in website_keywords_values {
-            query = query.bind(keyword_id).bind(website_id).bind(keyword_occurrences);
+    // Prepare data for bulk insert of links, merging links to the same target
+    let mut merged_links: Vec<Link> = Vec::new();
+    for link in &links {
+        match merged_links.iter_mut().find(|merged| merged.url == link.url) {
+            Some(merged) => {
+                if !link.anchor_text.is_empty() {
+                    merged.anchor_text = format!("{} {}", merged.anchor_text, link.anchor_text)
+                        .trim()
+                        .to_string();
+                }
+                merged.title = merged.title.clone().or_else(|| link.title.clone());
+                merged.rel = merged.rel.clone().or_else(|| link.rel.clone());
+                // One followed link is enough for the edge to count
+                merged.nofollow = merged.nofollow && link.nofollow;
+                merged.anchor_terms.extend(link.anchor_terms.iter().cloned());
+            }
+            None => merged_links.push(link.clone()),
         }
-        query.execute(pool).await?;
     }
 
-    // Prepare data for bulk insert of links
-    let links_values: Vec<(i32, &String)> = links
-        .iter()
-        .map(|link| (website_id, link))
-        .collect();
-
     // Bulk insert links
-    if !links_values.is_empty() {
-        let insert_links_query = format!(
-            "INSERT INTO website_links (source_website_id, target_website) VALUES {} ON CONFLICT (source_website_id, target_website) DO NOTHING",
-            links_values
-                .iter()
-                .enumerate()
-                .map(|(i, _)| format!("(${}, ${})", i * 2 + 1, i * 2 + 2))
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
+    let mut link_ids: HashMap<String, i32> = HashMap::new();
+    if !merged_links.is_empty() {
+        match mode {
+            InsertMode::Copy => {
+                link_ids = bulk_load::copy_links(&mut *connection, website_id, &merged_links).await?;
+            }
+            InsertMode::Values => {
+                for chunk in merged_links.chunks(rows_per_statement(6)) {
+                    let mut query_builder = sqlx::QueryBuilder::new(
+                        "INSERT INTO website_links (source_website_id, target_website, anchor_text, title, rel, nofollow) "
+                    );
+                    query_builder.push_values(chunk, |mut b, link| {
+                        b.push_bind(website_id)
+                            .push_bind(&link.url)
+                            .push_bind(&link.anchor_text)
+                            .push_bind(&link.title)
+                            .push_bind(&link.rel)
+                            .push_bind(link.nofollow);
+                    });
+                    query_builder.push(
+                        " ON CONFLICT (source_website_id, target_website) DO NOTHING RETURNING id, target_website"
+                    );
+                    for row in query_builder.build().fetch_all(&mut *connection).await? {
+                        link_ids.insert(row.get(1), row.get(0));
+                    }
+                }
+            }
+        }
+    }
 
-        let mut query = sqlx::query(&insert_links_query);
-        for (source_website_id, target_website) in links_values {
-            query = query.bind(source_website_id).bind(target_website);
+    // Bulk insert the analyzed anchor text of each link
+    let mut link_keywords_values: Vec<(i32, i32, i32)> = Vec::new();
+    for link in &merged_links {
+        if let Some(&link_id) = link_ids.get(&link.url) {
+            let mut anchor_counts: HashMap<String, i32> = HashMap::new();
+            for term in &link.anchor_terms {
+                *anchor_counts.entry(truncate_keyword(term)).or_insert(0) += 1;
+            }
+            for (keyword, count) in anchor_counts {
+                if let Some(&keyword_id) = keyword_id_map.get(&keyword) {
+                    link_keywords_values.push((link_id, keyword_id, count));
+                }
+            }
         }
-        query.execute(pool).await?;
     }
 
+    if !link_keywords_values.is_empty() {
+        match mode {
+            InsertMode::Copy => {
+                bulk_load::copy_link_keywords(&mut *connection, &link_keywords_values).await?;
+            }
+            InsertMode::Values => {
+                for chunk in link_keywords_values.chunks(rows_per_statement(3)) {
+                    let mut query_builder = sqlx::QueryBuilder::new(
+                        "INSERT INTO link_keywords (link_id, keyword_id, occurrences) "
+                    );
+                    query_builder.push_values(chunk, |mut b, (link_id, keyword_id, occurrences)| {
+                        b.push_bind(link_id).push_bind(keyword_id).push_bind(occurrences);
+                    });
+                    query_builder.build().execute(&mut *connection).await?;
+                }
+            }
+        }
+    }
+
+    // Resolve the targets of this page's links, and links from other pages to this one
+    let resolve_outgoing_links_query: &str =
+        r#"
+    UPDATE website_links
+    SET target_website_id = websites.id
+    FROM websites
+    WHERE website_links.source_website_id = $1 AND websites.url = website_links.target_website
+    "#;
+    sqlx::query(resolve_outgoing_links_query).bind(website_id).execute(&mut *connection).await?;
+
+    let resolve_incoming_links_query: &str =
+        r#"
+    UPDATE website_links SET target_website_id = $1 WHERE target_website = $2
+    "#;
+    sqlx::query(resolve_incoming_links_query).bind(website_id).bind(&url).execute(&mut *connection).await?;
+
+    // Re-credit anchor text to this page and to every page it links or used to link to
+    let new_targets_query: &str =
+        r#"
+    SELECT DISTINCT target_website_id FROM website_links
+    WHERE source_website_id = $1 AND target_website_id IS NOT NULL
+    "#;
+    let mut credited_website_ids: Vec<i32> = sqlx
+        ::query_scalar(new_targets_query)
+        .bind(website_id)
+        .fetch_all(&mut *connection).await?;
+    credited_website_ids.extend(old_target_ids);
+    credited_website_ids.push(website_id);
+    credited_website_ids.sort_unstable();
+    credited_website_ids.dedup();
+    credit_anchor_text(&mut *connection, &credited_website_ids).await?;
+
     Ok(())
 }
 
-const BATCH_SIZE: usize = 1000; // Adjust the batch size as needed
-
-pub async fn fetch_files_to_process() -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
-    dotenv().ok();
-    let database_url: String = env
-        ::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set in the .env file");
-    let pool: Pool<Postgres> = PgPool::connect(&database_url).await.expect(
-        "Failed to connect to the database"
+// Delete the keywords of websites and take them out of the document frequencies
+async fn remove_website_keywords(
+    connection: &mut PgConnection,
+    website_ids: &[i32]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let remove_keywords_query: String = format!(
+        r#"
+    WITH removed AS (
+        DELETE FROM website_keywords WHERE website_id = ANY($1)
+        RETURNING keyword_id, {} AS counted
+    ),
+    documents AS (
+        SELECT keyword_id, COUNT(*) AS documents FROM removed WHERE counted GROUP BY keyword_id
+    )
+    UPDATE keywords
+    SET documents_containing_word = keywords.documents_containing_word - documents.documents
+    FROM documents
+    WHERE keywords.id = documents.keyword_id
+    "#,
+        COUNTS_AS_DOCUMENT
     );
+    sqlx::query(&remove_keywords_query).bind(website_ids).execute(&mut *connection).await?;
+    Ok(())
+}
 
-    // Check if the table exists
-    let table_exists_query =
+// Remove websites from the index, returning how many of the URLs were stored. Their keywords
+// leave the document frequencies and their anchor text is taken back from the pages they linked to.
+pub async fn delete_websites(
+    database: &Database,
+    urls: &[String]
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+    let mut transaction = pool.begin().await?;
+
+    let website_ids: Vec<i32> = sqlx
+        ::query_scalar("SELECT id FROM websites WHERE url = ANY($1)")
+        .bind(urls)
+        .fetch_all(&mut *transaction).await?;
+    if website_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let targets_query: &str =
         r#"
-        SELECT EXISTS (
-            SELECT FROM information_schema.tables 
-            WHERE table_name = 'files'
-        )
+    SELECT DISTINCT target_website_id FROM website_links
+    WHERE source_website_id = ANY($1)
+        AND target_website_id IS NOT NULL
+        AND NOT target_website_id = ANY($1)
     "#;
-    let table_exists: bool = sqlx::query_scalar(table_exists_query).fetch_one(&pool).await?;
+    let target_ids: Vec<i32> = sqlx
+        ::query_scalar(targets_query)
+        .bind(&website_ids)
+        .fetch_all(&mut *transaction).await?;
 
-    if !table_exists {
-        // Create table if it doesn't exist
-        let create_table_query =
-            r#"
-            CREATE TABLE files (
-                id SERIAL PRIMARY KEY,
-                file_name TEXT NOT NULL UNIQUE,
-                processed BOOLEAN DEFAULT FALSE
-            )
-        "#;
-        pool.execute(create_table_query).await?;
+    remove_website_keywords(&mut transaction, &website_ids).await?;
+    sqlx::query("DELETE FROM website_links WHERE source_website_id = ANY($1)")
+        .bind(&website_ids)
+        .execute(&mut *transaction).await?;
+    // Aliases go with the website, and links to it or copies of it are unset
+    sqlx::query("DELETE FROM websites WHERE id = ANY($1)")
+        .bind(&website_ids)
+        .execute(&mut *transaction).await?;
+    credit_anchor_text(&mut transaction, &target_ids).await?;
+
+    transaction.commit().await?;
+    Ok(website_ids.len())
+}
 
+// Rebuild the anchor text credited to websites from the links pointing at them.
+// Keywords that only appear in anchor text get a row with zero occurrences in every other field.
+async fn credit_anchor_text(
+    connection: &mut PgConnection,
+    website_ids: &[i32]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let clear_anchor_only_query: &str =
+        r#"
+    DELETE FROM website_keywords
+    WHERE website_id = ANY($1)
+        AND keyword_occurrences = 0
+        AND title_occurrences = 0
+        AND description_occurrences = 0
+        AND heading_occurrences = 0
+        AND url_occurrences = 0
+    "#;
+    sqlx::query(clear_anchor_only_query).bind(website_ids).execute(&mut *connection).await?;
+
+    let reset_anchor_query: &str =
+        r#"
+    UPDATE website_keywords SET anchor_occurrences = 0 WHERE website_id = ANY($1)
+    "#;
+    sqlx::query(reset_anchor_query).bind(website_ids).execute(&mut *connection).await?;
+
+    let credit_query: &str =
+        r#"
+    WITH credits AS (
+        SELECT website_links.target_website_id AS website_id,
+            link_keywords.keyword_id,
+            SUM(link_keywords.occurrences)::INT AS occurrences
+        FROM link_keywords
+        JOIN website_links ON website_links.id = link_keywords.link_id
+        WHERE website_links.target_website_id = ANY($1)
+        GROUP BY website_links.target_website_id, link_keywords.keyword_id
+    )
+    INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences, anchor_occurrences)
+    SELECT keyword_id, website_id, 0, occurrences FROM credits
+    ORDER BY website_id, keyword_id
+    ON CONFLICT (keyword_id, website_id) DO UPDATE SET anchor_occurrences = EXCLUDED.anchor_occurrences
+    "#;
+    sqlx::query(credit_query).bind(website_ids).execute(&mut *connection).await?;
+
+    Ok(())
+}
+
+// Occurrences of a keyword in each indexed field of a webpage
+#[derive(Debug, Default)]
+struct KeywordStats {
+    body_positions: Vec<i32>,
+    title_occurrences: i32,
+    description_occurrences: i32,
+    heading_occurrences: i32,
+    url_occurrences: i32,
+}
+
+// Count every keyword of a webpage per field, keyed by the truncated keyword
+fn keyword_stats(webpage: &Webpage) -> HashMap<String, KeywordStats> {
+    let mut stats: HashMap<String, KeywordStats> = HashMap::new();
+
+    let body: &[Token] = webpage.lemmatised_text.as_deref().unwrap_or_default();
+    for token in body {
+        stats.entry(truncate_keyword(&token.text)).or_default().body_positions.push(token.position as i32);
+    }
+
+    let default_field_terms: FieldTerms = FieldTerms::default();
+    let field_terms: &FieldTerms = webpage.field_terms.as_ref().unwrap_or(&default_field_terms);
+    for keyword in &field_terms.title {
+        stats.entry(truncate_keyword(keyword)).or_default().title_occurrences += 1;
+    }
+    for keyword in &field_terms.description {
+        stats.entry(truncate_keyword(keyword)).or_default().description_occurrences += 1;
+    }
+    for keyword in &field_terms.headings {
+        stats.entry(truncate_keyword(keyword)).or_default().heading_occurrences += 1;
+    }
+    for keyword in &field_terms.url {
+        stats.entry(truncate_keyword(keyword)).or_default().url_occurrences += 1;
+    }
+    stats
+}
+
+// How many rows fit in one statement, as sqlx panics on more bind parameters than Postgres takes
+fn rows_per_statement(parameters_per_row: usize) -> usize {
+    MAX_BIND_PARAMETERS / parameters_per_row
+}
+
+// Truncate keywords to maximum length, without splitting a character
+fn truncate_keyword(keyword: &str) -> String {
+    if keyword.len() <= MAX_KEYWORD_LENGTH {
+        return keyword.to_string();
+    }
+    let mut end: usize = MAX_KEYWORD_LENGTH;
+    while !keyword.is_char_boundary(end) {
+        end -= 1;
+    }
+    keyword[..end].to_string()
+}
+
+// Store ascending word positions as the first position followed by the gap to each next one
+fn delta_encode(positions: &[i32]) -> Vec<i32> {
+    let mut previous: i32 = 0;
+    positions
+        .iter()
+        .map(|&position| {
+            let delta: i32 = position - previous;
+            previous = position;
+            delta
+        })
+        .collect()
+}
+
+const BATCH_SIZE: usize = 1000; // Adjust the batch size as needed
+// Longest redirect chain followed when resolving links
+const MAX_REDIRECT_HOPS: i32 = 10;
+
+pub async fn fetch_files_to_process(
+    database: &Database,
+    kind: InputKind
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+
+    create_files_table(pool).await?;
+
+    // Each kind of file is listed once, from its own paths file such as `wet.paths`.
+    // Local files don't count, they are tracked in the same table but never listed there.
+    let kind_listed_query: &str = "SELECT EXISTS (SELECT 1 FROM files WHERE kind = $1 AND is_local = FALSE)";
+    let kind_listed: bool = sqlx
+        ::query_scalar(kind_listed_query)
+        .bind(kind.as_str())
+        .fetch_one(pool).await?;
+    if !kind_listed {
         // Fetch file names from the specified source
-        let files: Vec<String> = helper_functions::fetch_lines(0, "warc.paths").unwrap();
-
-        // Insert the fetched file names into the database if they do not exist using batches
-        for chunk in files.chunks(BATCH_SIZE) {
-            let mut query_builder = sqlx::QueryBuilder::new("INSERT INTO files (file_name) ");
-            query_builder.push_values(chunk, |mut b, file| {
-                b.push_bind(file);
-            });
-            query_builder.push("ON CONFLICT (file_name) DO NOTHING");
-            query_builder.build().execute(&pool).await?;
-        }
+        let files: Vec<String> = helper_functions
+            ::fetch_lines(0, &kind.paths_file())
+            .map_err(|e| format!("Failed to read {}: {}", kind.paths_file(), e))?;
+        insert_files(pool, &files, kind, false).await?;
     }
 
     // Fetch file names that haven't been processed
     let query = r#"
         SELECT file_name
         FROM files
-        WHERE processed = FALSE
+        WHERE processed = FALSE AND kind = $1 AND is_local = FALSE
     "#;
 
     let mut file_names: Vec<String> = Vec::new();
-    let mut rows = sqlx::query(query).fetch(&pool);
+    let mut rows = sqlx::query(query).bind(kind.as_str()).fetch(pool);
 
     while let Some(row) = rows.try_next().await? {
         let file_name: String = row.get(0);
@@ -328,14 +960,101 @@ pub async fn fetch_files_to_process() -> Result<Vec<String>, Box<dyn Error + Sen
     Ok(file_names)
 }
 
-pub async fn mark_file_as_processed(file_name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-    dotenv().ok();
-    let database_url: String = env
-        ::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set in the .env file");
-    let pool: Pool<Postgres> = PgPool::connect(&database_url).await.expect(
-        "Failed to connect to the database"
-    );
+pub async fn fetch_local_files_to_process(
+    database: &Database,
+    local_files: &[String],
+    kind: InputKind
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+
+    // Local files are tracked in the same table, keyed by their path and marked as local
+    create_files_table(pool).await?;
+    insert_files(pool, local_files, kind, true).await?;
+
+    // Fetch the local files that haven't been processed
+    let query = r#"
+        SELECT file_name
+        FROM files
+        WHERE processed = FALSE AND is_local = TRUE AND file_name = ANY($1)
+        ORDER BY file_name
+    "#;
+
+    let mut file_names: Vec<String> = Vec::new();
+    let mut rows = sqlx::query(query).bind(local_files).fetch(pool);
+
+    while let Some(row) = rows.try_next().await? {
+        let file_name: String = row.get(0);
+        file_names.push(file_name);
+    }
+
+    Ok(file_names)
+}
+
+// Create the files table if it doesn't exist yet
+async fn create_files_table(pool: &PgPool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Check if the table exists
+    let table_exists_query =
+        r#"
+        SELECT EXISTS (
+            SELECT FROM information_schema.tables 
+            WHERE table_name = 'files'
+        )
+    "#;
+    let table_exists: bool = sqlx::query_scalar(table_exists_query).fetch_one(pool).await?;
+
+    if table_exists {
+        return Ok(());
+    }
+
+    let create_table_query =
+        r#"
+        CREATE TABLE files (
+            id SERIAL PRIMARY KEY,
+            file_name TEXT NOT NULL UNIQUE,
+            processed BOOLEAN DEFAULT FALSE,
+            kind TEXT NOT NULL DEFAULT 'warc',
+            is_local BOOLEAN NOT NULL DEFAULT FALSE
+        )
+    "#;
+    pool.execute(create_table_query).await?;
+    Ok(())
+}
+
+// Insert file names into the database if they do not exist using batches
+async fn insert_files(
+    pool: &PgPool,
+    files: &[String],
+    kind: InputKind,
+    is_local: bool
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for chunk in files.chunks(BATCH_SIZE) {
+        let mut query_builder = sqlx::QueryBuilder::new("INSERT INTO files (file_name, kind, is_local) ");
+        query_builder.push_values(chunk, |mut b, file| {
+            b.push_bind(file).push_bind(kind.as_str()).push_bind(is_local);
+        });
+        query_builder.push("ON CONFLICT (file_name) DO NOTHING");
+        query_builder.build().execute(pool).await?;
+    }
+    Ok(())
+}
+
+// The WARC-Record-IDs of the pages of a file that are already stored
+pub async fn fetch_ingested_records(
+    database: &Database,
+    file_name: &str
+) -> Result<HashSet<String>, Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+
+    let query: &str = "SELECT record_id FROM ingested_records WHERE file_name = $1";
+    let record_ids: Vec<String> = sqlx::query_scalar(query).bind(file_name).fetch_all(pool).await?;
+    Ok(record_ids.into_iter().collect())
+}
+
+pub async fn mark_file_as_processed(
+    database: &Database,
+    file_name: &str
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
 
     let query: &str =
         r#"
@@ -344,6 +1063,235 @@ pub async fn mark_file_as_processed(file_name: &str) -> Result<(), Box<dyn Error
         WHERE file_name = $1
     "#;
 
-    sqlx::query(query).bind(file_name).execute(&pool).await?;
+    // The ledger is only needed while a file is partly processed, so it is cleared in the same transaction
+    let delete_ledger_query: &str = "DELETE FROM ingested_records WHERE file_name = $1";
+
+    let mut transaction = pool.begin().await?;
+    sqlx::query(query).bind(file_name).execute(&mut *transaction).await?;
+    sqlx::query(delete_ledger_query).bind(file_name).execute(&mut *transaction).await?;
+    transaction.commit().await?;
     Ok(())
 }
+
+// Load every indexed website and the followed links between them.
+// Links recorded before their target was indexed are resolved to website IDs first,
+// following aliases and redirect chains where the URL itself wasn't indexed.
+pub async fn fetch_link_graph(database: &Database) -> Result<
+    (Vec<(i32, String)>, Vec<(i32, i32)>),
+    Box<dyn Error + Send + Sync>
+> {
+    let pool: &PgPool = database.pool();
+
+    let resolve_query: &str =
+        r#"
+        UPDATE website_links
+        SET target_website_id = websites.id
+        FROM websites
+        WHERE websites.url = website_links.target_website
+            AND website_links.target_website_id IS NULL
+    "#;
+    sqlx::query(resolve_query).execute(pool).await?;
+
+    let resolve_aliases_query: &str =
+        r#"
+        UPDATE website_links
+        SET target_website_id = website_aliases.website_id
+        FROM website_aliases
+        WHERE website_aliases.url = website_links.target_website
+            AND website_links.target_website_id IS NULL
+    "#;
+    sqlx::query(resolve_aliases_query).execute(pool).await?;
+
+    let resolve_redirects_query: &str =
+        r#"
+        WITH RECURSIVE chain (source_url, target_url, hops) AS (
+            SELECT source_url, target_url, 1 FROM redirects
+            UNION ALL
+            SELECT chain.source_url, redirects.target_url, chain.hops + 1
+            FROM chain
+            JOIN redirects ON redirects.source_url = chain.target_url
+            WHERE chain.hops < $1
+        ),
+        destinations AS (
+            SELECT DISTINCT ON (chain.source_url) chain.source_url, websites.id AS website_id
+            FROM chain
+            JOIN websites ON websites.url = chain.target_url
+            ORDER BY chain.source_url, chain.hops
+        )
+        UPDATE website_links
+        SET target_website_id = destinations.website_id
+        FROM destinations
+        WHERE destinations.source_url = website_links.target_website
+            AND website_links.target_website_id IS NULL
+    "#;
+    sqlx::query(resolve_redirects_query).bind(MAX_REDIRECT_HOPS).execute(pool).await?;
+
+    let websites: Vec<(i32, String)> = sqlx
+        ::query_as("SELECT id, url FROM websites ORDER BY id")
+        .fetch_all(pool).await?;
+
+    let links_query: &str =
+        r#"
+        SELECT DISTINCT source_website_id, target_website_id
+        FROM website_links
+        WHERE target_website_id IS NOT NULL AND NOT nofollow
+    "#;
+    let links: Vec<(i32, i32)> = sqlx::query_as(links_query).fetch_all(pool).await?;
+
+    Ok((websites, links))
+}
+
+pub async fn store_pagerank(
+    database: &Database,
+    scores: &[(i32, f64)]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+
+    let query: &str =
+        r#"
+        UPDATE websites
+        SET pagerank = scores.pagerank
+        FROM UNNEST($1::INT[], $2::DOUBLE PRECISION[]) AS scores(id, pagerank)
+        WHERE websites.id = scores.id
+    "#;
+
+    for chunk in scores.chunks(BATCH_SIZE) {
+        let (ids, values): (Vec<i32>, Vec<f64>) = chunk.iter().copied().unzip();
+        sqlx::query(query).bind(ids).bind(values).execute(pool).await?;
+    }
+    Ok(())
+}
+
+// Replace the host-level scores with a fresh rollup
+pub async fn store_host_pagerank(
+    database: &Database,
+    scores: &[(String, f64, i32)]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+
+    let mut transaction = pool.begin().await?;
+    sqlx::query("DELETE FROM host_pagerank").execute(&mut *transaction).await?;
+
+    for chunk in scores.chunks(BATCH_SIZE) {
+        let mut query_builder = sqlx::QueryBuilder::new(
+            "INSERT INTO host_pagerank (host, pagerank, page_count) "
+        );
+        query_builder.push_values(chunk, |mut b, (host, pagerank, page_count)| {
+            b.push_bind(host).push_bind(pagerank).push_bind(page_count);
+        });
+        query_builder.build().execute(&mut *transaction).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+// How far `keywords.documents_containing_word` had drifted from the rows in `website_keywords`
+pub struct DocumentFrequencyDrift {
+    pub keyword_count: i64,
+    // Keywords whose stored count was wrong, and how many of those were too high
+    pub drifted_count: i64,
+    pub overcounted_count: i64,
+    // Sum of the differences between the stored and rebuilt counts, ignoring their sign
+    pub total_drift: i64,
+    // The keywords that were furthest off, with their stored and rebuilt counts
+    pub largest_drifts: Vec<(String, i64, i64)>,
+}
+
+// Number of keywords listed in the drift report
+const LARGEST_DRIFTS_SHOWN: i64 = 10;
+
+// Rebuild the document frequency of every keyword by counting its rows in `website_keywords`.
+// Writers are blocked while this runs, so the counts match the rows they were rebuilt from.
+pub async fn recompute_document_frequencies(
+    database: &Database
+) -> Result<DocumentFrequencyDrift, Box<dyn Error + Send + Sync>> {
+    let pool: &PgPool = database.pool();
+    let mut transaction = pool.begin().await?;
+
+    // Every page written takes row locks on keywords, which this mode waits for and then blocks
+    sqlx::query("LOCK TABLE keywords IN SHARE ROW EXCLUSIVE MODE").execute(&mut *transaction).await?;
+
+    let drift_query: String = format!(
+        r#"
+        CREATE TEMPORARY TABLE document_frequency_drift ON COMMIT DROP AS
+        SELECT keywords.id, keywords.word,
+            COALESCE(keywords.documents_containing_word, 0) AS stored,
+            COALESCE(counted.documents, 0) AS rebuilt
+        FROM keywords
+        LEFT JOIN (
+            SELECT keyword_id, COUNT(*) AS documents
+            FROM website_keywords
+            WHERE {}
+            GROUP BY keyword_id
+        ) AS counted ON counted.keyword_id = keywords.id
+        WHERE keywords.documents_containing_word IS DISTINCT FROM COALESCE(counted.documents, 0)
+    "#,
+        COUNTS_AS_DOCUMENT
+    );
+    sqlx::query(&drift_query).execute(&mut *transaction).await?;
+
+    let keyword_count: i64 = sqlx
+        ::query_scalar("SELECT COUNT(*) FROM keywords")
+        .fetch_one(&mut *transaction).await?;
+
+    let summary_query: &str =
+        r#"
+        SELECT COUNT(*),
+            COUNT(*) FILTER (WHERE stored > rebuilt),
+            COALESCE(SUM(ABS(stored - rebuilt)), 0)::BIGINT
+        FROM document_frequency_drift
+    "#;
+    let (drifted_count, overcounted_count, total_drift): (i64, i64, i64) = sqlx
+        ::query_as(summary_query)
+        .fetch_one(&mut *transaction).await?;
+
+    let largest_query: &str =
+        r#"
+        SELECT word, stored, rebuilt
+        FROM document_frequency_drift
+        ORDER BY ABS(stored - rebuilt) DESC, word
+        LIMIT $1
+    "#;
+    let largest_drifts: Vec<(String, i64, i64)> = sqlx
+        ::query_as(largest_query)
+        .bind(LARGEST_DRIFTS_SHOWN)
+        .fetch_all(&mut *transaction).await?;
+
+    let update_query: &str =
+        r#"
+        UPDATE keywords
+        SET documents_containing_word = document_frequency_drift.rebuilt
+        FROM document_frequency_drift
+        WHERE keywords.id = document_frequency_drift.id
+    "#;
+    sqlx::query(update_query).execute(&mut *transaction).await?;
+
+    transaction.commit().await?;
+    Ok(DocumentFrequencyDrift {
+        keyword_count,
+        drifted_count,
+        overcounted_count,
+        total_drift,
+        largest_drifts,
+    })
+}
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    MissingUrl,
+    InvalidUrl(sqlx::Error),
+    Connect(sqlx::Error),
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DatabaseError::MissingUrl => write!(f, "DATABASE_URL must be set in the .env file"),
+            DatabaseError::InvalidUrl(err) => write!(f, "Invalid DATABASE_URL: {}", err),
+            DatabaseError::Connect(err) => write!(f, "Failed to connect to the database: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
@@ -0,0 +1,299 @@
+use log::warn;
+use once_cell::sync::Lazy;
+use sqlx::{ Executor, PgConnection, Row };
+use std::collections::{ BTreeMap, HashMap };
+use std::env;
+use std::error::Error;
+
+use super::{ delta_encode, KeywordStats };
+use crate::helper_functions::Link;
+
+// How rows of a page are written to `keywords`, `website_keywords`, `website_links` and `link_keywords`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertMode {
+    // One `INSERT ... VALUES` statement per table with a bind parameter for every value
+    Values,
+    // `COPY ... FROM STDIN` into staging tables, merged into the live tables with one statement each
+    Copy,
+}
+
+impl InsertMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InsertMode::Values => "values",
+            InsertMode::Copy => "copy",
+        }
+    }
+}
+
+// Read from `INSERT_MODE` as `copy` or `values`, defaulting to `copy`
+pub static INSERT_MODE: Lazy<InsertMode> = Lazy::new(|| {
+    match env::var("INSERT_MODE").unwrap_or_default().to_lowercase().as_str() {
+        "values" => InsertMode::Values,
+        "copy" | "" => InsertMode::Copy,
+        other => {
+            warn!("Unknown INSERT_MODE {}, using copy", other);
+            InsertMode::Copy
+        }
+    }
+});
+
+// Staging tables live as long as their connection, so they are created once when it opens.
+// Each merge empties its staging table, whether it commits or rolls back.
+pub async fn create_staging_tables(connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let staging_tables_query: &str =
+        r#"
+    CREATE TEMPORARY TABLE IF NOT EXISTS staging_keywords (
+        word TEXT NOT NULL,
+        documents INT NOT NULL
+    );
+    CREATE TEMPORARY TABLE IF NOT EXISTS staging_website_keywords (
+        keyword_id INT NOT NULL,
+        website_id INT NOT NULL,
+        keyword_occurrences INT NOT NULL,
+        positions INT[] NOT NULL,
+        title_occurrences INT NOT NULL,
+        description_occurrences INT NOT NULL,
+        heading_occurrences INT NOT NULL,
+        url_occurrences INT NOT NULL
+    );
+    CREATE TEMPORARY TABLE IF NOT EXISTS staging_links (
+        source_website_id INT NOT NULL,
+        target_website TEXT NOT NULL,
+        anchor_text TEXT,
+        title TEXT,
+        rel TEXT,
+        nofollow BOOLEAN NOT NULL
+    );
+    CREATE TEMPORARY TABLE IF NOT EXISTS staging_link_keywords (
+        link_id INT NOT NULL,
+        keyword_id INT NOT NULL,
+        occurrences INT NOT NULL
+    )
+    "#;
+    connection.execute(staging_tables_query).await?;
+    Ok(())
+}
+
+// Rows in the text format of `COPY`: tab separated fields, one row per line and `\N` for NULL
+#[derive(Default)]
+struct CopyRows {
+    data: String,
+    in_row: bool,
+}
+
+impl CopyRows {
+    fn field<T: CopyField + ?Sized>(&mut self, value: &T) -> &mut Self {
+        if self.in_row {
+            self.data.push('\t');
+        }
+        value.write_copy(&mut self.data);
+        self.in_row = true;
+        self
+    }
+
+    fn end_row(&mut self) {
+        self.data.push('\n');
+        self.in_row = false;
+    }
+
+    // Send the rows with a `COPY ... FROM STDIN` statement, returning how many were written
+    async fn copy_into(self, connection: &mut PgConnection, statement: &str) -> Result<u64, sqlx::Error> {
+        let mut copy = connection.copy_in_raw(statement).await?;
+        copy.send(self.data.into_bytes()).await?;
+        copy.finish().await
+    }
+}
+
+trait CopyField {
+    fn write_copy(&self, data: &mut String);
+}
+
+impl CopyField for i32 {
+    fn write_copy(&self, data: &mut String) {
+        data.push_str(&self.to_string());
+    }
+}
+
+impl CopyField for bool {
+    fn write_copy(&self, data: &mut String) {
+        data.push(if *self { 't' } else { 'f' });
+    }
+}
+
+// Backslashes and the separators have to be escaped
+impl CopyField for str {
+    fn write_copy(&self, data: &mut String) {
+        for character in self.chars() {
+            match character {
+                '\\' => data.push_str("\\\\"),
+                '\t' => data.push_str("\\t"),
+                '\n' => data.push_str("\\n"),
+                '\r' => data.push_str("\\r"),
+                _ => data.push(character),
+            }
+        }
+    }
+}
+
+impl CopyField for String {
+    fn write_copy(&self, data: &mut String) {
+        self.as_str().write_copy(data);
+    }
+}
+
+impl<T: CopyField> CopyField for Option<T> {
+    fn write_copy(&self, data: &mut String) {
+        match self {
+            Some(value) => value.write_copy(data),
+            None => data.push_str("\\N"),
+        }
+    }
+}
+
+// Integer arrays such as `{3,7,2}`, which need no quoting
+impl CopyField for [i32] {
+    fn write_copy(&self, data: &mut String) {
+        let values: Vec<String> = self.iter().map(|value| value.to_string()).collect();
+        data.push('{');
+        data.push_str(&values.join(","));
+        data.push('}');
+    }
+}
+
+// Upsert keywords and their document counts, returning the id of each keyword
+pub(super) async fn copy_keywords(
+    connection: &mut PgConnection,
+    keyword_counts: &BTreeMap<String, i32>
+) -> Result<HashMap<String, i32>, Box<dyn Error + Send + Sync>> {
+    let mut rows: CopyRows = CopyRows::default();
+    for (keyword, count) in keyword_counts {
+        rows.field(keyword).field(count).end_row();
+    }
+    rows.copy_into(&mut *connection, "COPY staging_keywords (word, documents) FROM STDIN").await?;
+
+    // Rows are merged in word order, the same order as the values path locks them in
+    let merge_keywords_query: &str =
+        r#"
+    WITH staged AS (
+        DELETE FROM staging_keywords RETURNING word, documents
+    )
+    INSERT INTO keywords (word, documents_containing_word)
+    SELECT word, documents FROM staged ORDER BY word
+    ON CONFLICT (word) DO UPDATE
+        SET documents_containing_word = keywords.documents_containing_word + EXCLUDED.documents_containing_word
+    RETURNING id, word
+    "#;
+
+    let mut keyword_id_map: HashMap<String, i32> = HashMap::new();
+    for row in sqlx::query(merge_keywords_query).fetch_all(&mut *connection).await? {
+        keyword_id_map.insert(row.get(1), row.get(0));
+    }
+    Ok(keyword_id_map)
+}
+
+pub(super) async fn copy_website_keywords(
+    connection: &mut PgConnection,
+    website_id: i32,
+    website_keywords_values: &[(i32, KeywordStats)]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut rows: CopyRows = CopyRows::default();
+    for (keyword_id, stats) in website_keywords_values {
+        rows.field(keyword_id)
+            .field(&website_id)
+            .field(&(stats.body_positions.len() as i32))
+            .field(delta_encode(&stats.body_positions).as_slice())
+            .field(&stats.title_occurrences)
+            .field(&stats.description_occurrences)
+            .field(&stats.heading_occurrences)
+            .field(&stats.url_occurrences)
+            .end_row();
+    }
+    rows.copy_into(
+        &mut *connection,
+        "COPY staging_website_keywords (keyword_id, website_id, keyword_occurrences, positions, title_occurrences, description_occurrences, heading_occurrences, url_occurrences) FROM STDIN"
+    ).await?;
+
+    let merge_website_keywords_query: &str =
+        r#"
+    WITH staged AS (
+        DELETE FROM staging_website_keywords RETURNING *
+    )
+    INSERT INTO website_keywords (keyword_id, website_id, keyword_occurrences, positions, title_occurrences, description_occurrences, heading_occurrences, url_occurrences)
+    SELECT keyword_id, website_id, keyword_occurrences, positions, title_occurrences, description_occurrences, heading_occurrences, url_occurrences
+    FROM staged
+    ON CONFLICT (keyword_id, website_id) DO UPDATE
+        SET keyword_occurrences = EXCLUDED.keyword_occurrences,
+            positions = EXCLUDED.positions,
+            title_occurrences = EXCLUDED.title_occurrences,
+            description_occurrences = EXCLUDED.description_occurrences,
+            heading_occurrences = EXCLUDED.heading_occurrences,
+            url_occurrences = EXCLUDED.url_occurrences
+    "#;
+    sqlx::query(merge_website_keywords_query).execute(&mut *connection).await?;
+    Ok(())
+}
+
+// Insert the links of a website, returning the id of each link by its target
+pub(super) async fn copy_links(
+    connection: &mut PgConnection,
+    website_id: i32,
+    links: &[Link]
+) -> Result<HashMap<String, i32>, Box<dyn Error + Send + Sync>> {
+    let mut rows: CopyRows = CopyRows::default();
+    for link in links {
+        rows.field(&website_id)
+            .field(&link.url)
+            .field(&link.anchor_text)
+            .field(&link.title)
+            .field(&link.rel)
+            .field(&link.nofollow)
+            .end_row();
+    }
+    rows.copy_into(
+        &mut *connection,
+        "COPY staging_links (source_website_id, target_website, anchor_text, title, rel, nofollow) FROM STDIN"
+    ).await?;
+
+    let merge_links_query: &str =
+        r#"
+    WITH staged AS (
+        DELETE FROM staging_links RETURNING *
+    )
+    INSERT INTO website_links (source_website_id, target_website, anchor_text, title, rel, nofollow)
+    SELECT source_website_id, target_website, anchor_text, title, rel, nofollow FROM staged
+    ON CONFLICT (source_website_id, target_website) DO NOTHING
+    RETURNING id, target_website
+    "#;
+    let mut link_ids: HashMap<String, i32> = HashMap::new();
+    for row in sqlx::query(merge_links_query).fetch_all(&mut *connection).await? {
+        link_ids.insert(row.get(1), row.get(0));
+    }
+    Ok(link_ids)
+}
+
+pub(super) async fn copy_link_keywords(
+    connection: &mut PgConnection,
+    link_keywords_values: &[(i32, i32, i32)]
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut rows: CopyRows = CopyRows::default();
+    for (link_id, keyword_id, occurrences) in link_keywords_values {
+        rows.field(link_id).field(keyword_id).field(occurrences).end_row();
+    }
+    rows.copy_into(
+        &mut *connection,
+        "COPY staging_link_keywords (link_id, keyword_id, occurrences) FROM STDIN"
+    ).await?;
+
+    let merge_link_keywords_query: &str =
+        r#"
+    WITH staged AS (
+        DELETE FROM staging_link_keywords RETURNING *
+    )
+    INSERT INTO link_keywords (link_id, keyword_id, occurrences)
+    SELECT link_id, keyword_id, occurrences FROM staged
+    ON CONFLICT (link_id, keyword_id) DO UPDATE SET occurrences = EXCLUDED.occurrences
+    "#;
+    sqlx::query(merge_link_keywords_query).execute(&mut *connection).await?;
+    Ok(())
+}
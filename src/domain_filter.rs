@@ -0,0 +1,137 @@
+use publicsuffix::{ List, Psl };
+use regex::Regex;
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use crate::helper_functions::fetch_lines;
+
+const DEFAULT_ALLOWLIST_PATH: &str = "top-1m.txt";
+const DEFAULT_ALLOWLIST_SIZE: usize = 100_000;
+const DEFAULT_PUBLIC_SUFFIX_LIST_PATH: &str = "public_suffix_list.dat";
+
+// Decides which hosts are indexed. Loaded once at startup and shared by every task.
+pub struct DomainFilter {
+    // None allows every host that isn't denied
+    allowlist: Option<DomainRules>,
+    denylist: DomainRules,
+    suffix_list: Option<List>,
+}
+
+impl DomainFilter {
+    // Read the lists named by `ALLOWLIST_PATH`, `ALLOWLIST_SIZE`, `DENYLIST_PATH` and
+    // `PUBLIC_SUFFIX_LIST_PATH`. An empty `ALLOWLIST_PATH` allows every domain and an empty
+    // `PUBLIC_SUFFIX_LIST_PATH` turns off registrable domain matching.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let allowlist_path: String = env
+            ::var("ALLOWLIST_PATH")
+            .unwrap_or_else(|_| DEFAULT_ALLOWLIST_PATH.to_string());
+        let allowlist_size: usize = env
+            ::var("ALLOWLIST_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_ALLOWLIST_SIZE);
+
+        let allowlist: Option<DomainRules> = if allowlist_path.is_empty() {
+            None
+        } else {
+            Some(DomainRules::parse(&fetch_lines(allowlist_size, &allowlist_path)?)?)
+        };
+        let denylist: DomainRules = match env::var("DENYLIST_PATH") {
+            Ok(path) if !path.is_empty() => DomainRules::parse(&fetch_lines(0, &path)?)?,
+            _ => DomainRules::default(),
+        };
+
+        let suffix_list_path: String = env
+            ::var("PUBLIC_SUFFIX_LIST_PATH")
+            .unwrap_or_else(|_| DEFAULT_PUBLIC_SUFFIX_LIST_PATH.to_string());
+        let suffix_list: Option<List> = if suffix_list_path.is_empty() {
+            None
+        } else {
+            let contents: String = fs
+                ::read_to_string(&suffix_list_path)
+                .map_err(|e| format!("Failed to read the Public Suffix List at {}: {}", suffix_list_path, e))?;
+            Some(contents.parse().map_err(|e| format!("{:?}", e))?)
+        };
+
+        Ok(DomainFilter { allowlist, denylist, suffix_list })
+    }
+
+    pub fn is_allowed(&self, host: &str) -> bool {
+        let host: String = host.trim_end_matches('.').to_lowercase();
+        let registrable_domain: Option<String> = self.suffix_list.as_ref().map(|list| {
+            list.domain(host.as_bytes())
+                .map(|domain| String::from_utf8_lossy(domain.as_bytes()).to_string())
+                .unwrap_or_else(|| host.clone())
+        });
+
+        if self.denylist.matches(&host, registrable_domain.as_deref()) {
+            return false;
+        }
+        self.allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.matches(&host, registrable_domain.as_deref()))
+    }
+}
+
+// One list of rules, one per line:
+// `example.com` matches the host and every host with the same registrable domain,
+// `.example.com` matches the domain and all of its subdomains,
+// `*.example.com` is a pattern where `*` matches any characters.
+// Lines starting with `#` are comments, and ranked lists such as `1,example.com` are accepted.
+#[derive(Default)]
+struct DomainRules {
+    domains: HashSet<String>,
+    suffixes: HashSet<String>,
+    patterns: Vec<Regex>,
+}
+
+impl DomainRules {
+    fn parse(lines: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut rules: DomainRules = DomainRules::default();
+
+        for line in lines {
+            let line: &str = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule: String = line.rsplit(',').next().unwrap_or_default().trim().to_lowercase();
+
+            if rule.contains('*') {
+                let pattern: String = rule
+                    .split('*')
+                    .map(regex::escape)
+                    .collect::<Vec<_>>()
+                    .join(".*");
+                rules.patterns.push(Regex::new(&format!("^{}$", pattern))?);
+            } else if let Some(suffix) = rule.strip_prefix('.') {
+                rules.suffixes.insert(suffix.to_string());
+            } else {
+                rules.domains.insert(rule);
+            }
+        }
+        Ok(rules)
+    }
+
+    // Without a registrable domain from the Public Suffix List, plain domains match like suffixes
+    fn matches(&self, host: &str, registrable_domain: Option<&str>) -> bool {
+        if self.domains.contains(host) {
+            return true;
+        }
+        let in_domains: bool = match registrable_domain {
+            Some(domain) => self.domains.contains(domain),
+            None => parent_domains(host).any(|parent| self.domains.contains(parent)),
+        };
+
+        in_domains ||
+            parent_domains(host).any(|parent| self.suffixes.contains(parent)) ||
+            self.suffixes.contains(host) ||
+            self.patterns.iter().any(|pattern| pattern.is_match(host))
+    }
+}
+
+// `a.b.example.com` gives `b.example.com`, `example.com` and `com`
+fn parent_domains(host: &str) -> impl Iterator<Item = &str> {
+    host.match_indices('.').map(move |(index, _)| &host[index + 1..])
+}
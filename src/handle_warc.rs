@@ -1,94 +1,203 @@
 use log::warn;
-use reqwest::Client;
-use warc::WarcReader;
+use warc::{ WarcReader, WarcHeader, Record, BufferedBody };
 use std::error::Error;
 use std::path::{ Path, PathBuf };
 use std::time::{ Instant, Duration };
-use std::collections::HashSet;
+use std::collections::{ HashMap, HashSet };
 use indicatif::{ MultiProgress, ProgressBar, ProgressStyle };
-use std::fs::create_dir_all;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use std::fs::read_dir;
+use tokio::sync::mpsc::Sender;
 use std::sync::Arc;
 use colored::*;
+use once_cell::sync::Lazy;
+use std::env;
+use url::Url;
 
-use crate::helper_functions::{ fetch_lines, extract_domain_from_string, file_path_to_number };
+use crate::canonical_url;
+use crate::database::{ self, Database };
+use crate::handle_warc::http_response::HttpResponse;
+use crate::domain_filter::DomainFilter;
+use crate::helper_functions::{ extract_domain_from_string, file_path_to_number };
+pub mod archive_source;
+pub mod charset;
+pub mod download;
+pub mod http_response;
+pub mod robots;
+pub mod wat;
 pub mod webpage;
+pub mod wet;
 
-pub async fn download_warc_file(
-    file_name: &str,
-    multibar: &Arc<MultiProgress>
-) -> Result<PathBuf, Box<dyn Error>> {
-    // Create the URL
-    let url: String = format!("https://data.commoncrawl.org/{}", file_name);
-
-    // Initialize HTTP client
-    let client: Client = Client::new();
-
-    // Create the directory for storing the files
-    let dir_path: &Path = Path::new("warc_files");
-    create_dir_all(&dir_path)?;
-
-    // Extract the filename from the file path
-    let path: &Path = Path::new(file_name);
-    let file_name: std::borrow::Cow<str> = path
-        .file_name()
-        .ok_or("Invalid file name")?
-        .to_string_lossy();
-
-    // Create file paths
-    let file_path: PathBuf = dir_path.join(file_name.to_string()).with_extension("gz");
-
-    // Download the file
-    let mut response: reqwest::Response = client.get(&url).send().await?.error_for_status()?;
-    let mut output_file: File = File::create(&file_path).await?;
-
-    let parts: Vec<&str> = file_name.split("-").collect();
-    let file_number: Vec<&str> = parts[parts.len() - 1].split(".").collect();
-    let file_number: &str = file_number[0];
-
-    let progress_bar: ProgressBar = multibar.add(
-        ProgressBar::new(
-            response
-                .headers()
-                .get("Content-Length")
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .parse::<u64>()
-                .unwrap()
-        )
-    );
-    progress_bar.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                &format!(
-                    "Downloading {}: [{{elapsed_precise}}] [{{wide_bar:40.cyan/blue}}] {{bytes}}/{{total_bytes}} | Speed: {{bytes_per_sec}} | Time Left: {{eta}}",
-                    file_number.to_string().green().bold()
-                )
-            )
-            .unwrap()
-            .progress_chars("#>-")
-    );
+// Number of parsed webpages sent to the database writer at a time
+pub const WEBPAGE_BATCH_SIZE: usize = 100;
 
-    while let Some(chunk) = response.chunk().await? {
-        progress_bar.inc(chunk.len() as u64);
-        output_file.write_all(&chunk).await?;
+// Webpages and aliases handed from the WARC reader to the database writer
+#[derive(Debug, Default)]
+pub struct WebpageBatch {
+    pub webpages: Vec<webpage::Webpage>,
+    pub aliases: Vec<WebsiteAlias>,
+    pub redirects: Vec<Redirect>,
+    // URLs that answered with a 4xx or 5xx status, when `STORE_ERROR_STATUSES` is set
+    pub error_statuses: Vec<(String, i32)>,
+}
+
+impl WebpageBatch {
+    fn len(&self) -> usize {
+        self.webpages.len() + self.aliases.len() + self.redirects.len() + self.error_statuses.len()
     }
-    progress_bar.println(
-        format!("{}{}", "Downloaded file to: ".green().bold(), file_path.to_string_lossy().blue())
-    );
-    progress_bar.finish_and_clear();
-    // Return the path to the extracted file
-    Ok(file_path)
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// A URL whose content is the same as a page that was already parsed
+#[derive(Debug, Clone)]
+pub struct WebsiteAlias {
+    pub url: String,
+    pub original: AliasOf,
+}
+
+#[derive(Debug, Clone)]
+pub enum AliasOf {
+    // The stored page with this WARC-Payload-Digest
+    PayloadDigest(String),
+    // The stored page with this URL, for revisits without a digest
+    Url(String),
 }
 
-pub async fn read_warc_file(
+// A 3xx response and the URL its `Location` header points at
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub source_url: String,
+    pub target_url: String,
+    pub status_code: i32,
+}
+
+impl Redirect {
+    fn from_response(target_uri: &str, response: &HttpResponse) -> Option<Self> {
+        let source: Url = Url::parse(target_uri).ok()?;
+        let target: Url = source.join(response.header("Location")?.trim()).ok()?;
+        let source_url: String = canonical_url::canonicalise(&source).to_string();
+        let target_url: String = canonical_url::canonicalise(&target).to_string();
+
+        // Redirects such as http to https disappear once both URLs are canonical
+        if source_url == target_url {
+            return None;
+        }
+        Some(Redirect {
+            source_url,
+            target_url,
+            status_code: response.status_code? as i32,
+        })
+    }
+}
+
+// Header naming the URL a revisit record repeats
+const REFERS_TO_TARGET_URI: &str = "warc-refers-to-target-uri";
+
+// Store URLs that answered with a 4xx or 5xx status in `url_statuses`, off unless set to `true`
+static STORE_ERROR_STATUSES: Lazy<bool> = Lazy::new(|| {
+    env::var("STORE_ERROR_STATUSES").is_ok_and(|value| value == "true")
+});
+
+// The kind of Common Crawl file being indexed, read from `INPUT_KIND`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputKind {
+    // Full HTTP responses, parsed as HTML
+    Warc,
+    // Extracted plain text, indexed without parsing HTML
+    Wet,
+    // JSON metadata with the title, meta tags and links of each page
+    Wat,
+}
+
+impl InputKind {
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        match env::var("INPUT_KIND").unwrap_or_default().to_lowercase().as_str() {
+            "warc" | "" => Ok(InputKind::Warc),
+            "wet" => Ok(InputKind::Wet),
+            "wat" => Ok(InputKind::Wat),
+            other => Err(format!("Unsupported INPUT_KIND: {}", other).into()),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InputKind::Warc => "warc",
+            InputKind::Wet => "wet",
+            InputKind::Wat => "wat",
+        }
+    }
+
+    // The Common Crawl listing of files of this kind, such as `wet.paths`
+    pub fn paths_file(&self) -> String {
+        format!("{}.paths", self.as_str())
+    }
+
+    fn file_suffixes(&self) -> [&'static str; 2] {
+        match self {
+            InputKind::Warc => [".warc.gz", ".warc"],
+            InputKind::Wet => [".warc.wet.gz", ".warc.wet"],
+            InputKind::Wat => [".warc.wat.gz", ".warc.wat"],
+        }
+    }
+}
+
+// Collect local files of the given kind from a directory or a glob pattern
+pub fn find_local_warc_files(input: &str, kind: InputKind) -> Result<Vec<String>, Box<dyn Error>> {
+    let input_path: &Path = Path::new(input);
+    let mut paths: Vec<PathBuf> = Vec::new();
+
+    if input_path.is_dir() {
+        for entry in read_dir(input_path)? {
+            paths.push(entry?.path());
+        }
+    } else {
+        for entry in glob::glob(input)? {
+            paths.push(entry?);
+        }
+    }
+
+    let mut files: Vec<String> = paths
+        .into_iter()
+        .filter(|path| path.is_file() && is_input_file(path, kind))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+fn is_input_file(path: &Path, kind: InputKind) -> bool {
+    let file_name: String = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    kind.file_suffixes()
+        .iter()
+        .any(|suffix| file_name.ends_with(suffix))
+}
+
+// Read a WARC, WET or WAT file and stream its webpages in batches to the database writer.
+// This blocks on reading and parsing, so it should be run with `spawn_blocking`.
+pub fn read_warc_file(
     file_path: &Path,
-    multibar: &Arc<MultiProgress>
-) -> Result<Vec<webpage::Webpage>, Box<dyn Error>> {
-    let top_websites: HashSet<String> = fetch_lines(100_000, "top-1m.txt")?.into_iter().collect();
+    kind: InputKind,
+    domain_filter: &DomainFilter,
+    database: &Database,
+    ingested_records: &HashSet<String>,
+    multibar: &Arc<MultiProgress>,
+    sender: Sender<WebpageBatch>
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    // Payload digests are looked up in the database from this blocking thread, once per batch
+    let runtime: tokio::runtime::Handle = tokio::runtime::Handle::current();
+    let mut seen_digests: HashSet<String> = HashSet::new();
+    let mut duplicate_count: usize = 0;
+    let mut resumed_count: usize = 0;
+    let mut record_type_counts: HashMap<String, usize> = HashMap::new();
+    let mut status_counts: HashMap<String, usize> = HashMap::new();
+
     let mut count: i32 = 0;
+    let mut valid_count: usize = 0;
+    let mut charset_counts: HashMap<String, usize> = HashMap::new();
+    let mut description_source_counts: HashMap<String, usize> = HashMap::new();
     let mut matching_count: i32 = 0;
     let mut start: Instant = Instant::now();
     let time_taken: Instant = Instant::now();
@@ -100,16 +209,26 @@ pub async fn read_warc_file(
         ProgressStyle::default_bar()
             .template(
                 &format!(
-                    "{}: [{{elapsed_precise}}] [{{wide_bar:40.cyan/blue}}] Records read: {{pos}}/≈{{len}} | Time Left: {{eta}} | {{msg}}",
-                    format!("Processing {}", file_number.to_string().green().bold())
+                    "Processing {}: [{{elapsed_precise}}] [{{wide_bar:40.cyan/blue}}] Records read: {{pos}}/≈{{len}} | Time Left: {{eta}} | {{msg}}",
+                    file_number.to_string().green().bold()
                 )
             )
             .unwrap()
             .progress_chars("#>-")
     );
     progress_bar.tick();
-    let mut results: Vec<webpage::Webpage> = Vec::new();
-    for record in WarcReader::from_path_gzip(file_path)?.iter_records() {
+    let mut batch: WebpageBatch = WebpageBatch::default();
+
+    // Only .gz files are treated as gzipped, plain .warc, .wet and .wat files are read as-is
+    let records: Box<dyn Iterator<Item = Result<Record<BufferedBody>, warc::Error>>> = if
+        file_path.extension().is_some_and(|extension| extension != "gz")
+    {
+        Box::new(WarcReader::from_path(file_path)?.iter_records())
+    } else {
+        Box::new(WarcReader::from_path_gzip(file_path)?.iter_records())
+    };
+
+    for record in records {
         match record {
             Err(err) => warn!("ERROR: {}", err),
             Ok(record) => {
@@ -118,26 +237,149 @@ pub async fn read_warc_file(
                     None => String::new(),
                 };
 
-                match extract_domain_from_string(&target_uri) {
-                    Some(domain) => {
-                        if top_websites.contains(&domain) {
-                            if let Ok(webpage) = webpage::Webpage::parse_record(&record) {
+                // Pages written by an earlier, interrupted run of this file are not read again
+                let already_ingested: bool = record
+                    .header(WarcHeader::RecordID)
+                    .is_some_and(|record_id| ingested_records.contains(record_id.as_ref()));
+                if already_ingested {
+                    resumed_count += 1;
+                }
+
+                if let Some(domain) = extract_domain_from_string(&target_uri) {
+                    if !already_ingested && domain_filter.is_allowed(&domain) {
+                        let warc_type: String = record
+                            .header(WarcHeader::WarcType)
+                            .map(|warc_type| warc_type.to_string())
+                            .unwrap_or_default();
+                        *record_type_counts.entry(warc_type.clone()).or_insert(0) += 1;
+
+                        match warc_type.as_str() {
+                            "response" =>
+                                match HttpResponse::parse(record.body()) {
+                                    Err(err) => warn!("Skipping {}: {}", target_uri, err),
+                                    Ok(response) => {
+                                        let status_code: usize = response.status_code.unwrap_or_default();
+                                        *status_counts.entry(status_code.to_string()).or_insert(0) += 1;
+
+                                        match status_code {
+                                            300..=399 => {
+                                                if let Some(redirect) = Redirect::from_response(&target_uri, &response) {
+                                                    batch.redirects.push(redirect);
+                                                }
+                                            }
+                                            400..=599 => {
+                                                if *STORE_ERROR_STATUSES {
+                                                    if let Some(url) = canonical_url::canonicalise_str(&target_uri) {
+                                                        batch.error_statuses.push((url, status_code as i32));
+                                                    }
+                                                }
+                                            }
+                                            _ => {
+                                                // Identical payloads in a file are only parsed once, later copies become aliases.
+                                                // Copies of pages stored from other files are found when the batch is sent.
+                                                let payload_digest: Option<String> = record
+                                                    .header(WarcHeader::PayloadDigest)
+                                                    .map(|digest| digest.to_string());
+                                                let is_duplicate: bool = payload_digest
+                                                    .as_ref()
+                                                    .is_some_and(|digest| seen_digests.contains(digest));
+
+                                                if is_duplicate {
+                                                    duplicate_count += 1;
+                                                    if
+                                                        let (Some(url), Some(payload_digest)) = (
+                                                            canonical_url::canonicalise_str(&target_uri),
+                                                            payload_digest,
+                                                        )
+                                                    {
+                                                        batch.aliases.push(WebsiteAlias {
+                                                            url,
+                                                            original: AliasOf::PayloadDigest(payload_digest),
+                                                        });
+                                                    }
+                                                } else if
+                                                    let Ok(webpage) = webpage::Webpage::parse_response(&record, response)
+                                                {
+                                                    matching_count += 1;
+                                                    if let Some(webpage) = webpage {
+                                                        if webpage.lemmatised_text.is_some() {
+                                                            valid_count += 1;
+                                                            if let Some(charset) = &webpage.charset {
+                                                                *charset_counts.entry(charset.clone()).or_insert(0) += 1;
+                                                            }
+                                                            if let Some(source) = webpage.description_source {
+                                                                *description_source_counts
+                                                                    .entry(source.as_str().to_string())
+                                                                    .or_insert(0) += 1;
+                                                            }
+                                                            if let Some(digest) = &webpage.payload_digest {
+                                                                seen_digests.insert(digest.clone());
+                                                            }
+                                                            batch.webpages.push(webpage);
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            // A revisit repeats an earlier capture, so its URL becomes an alias of the original
+                            "revisit" => {
+                                let original: Option<AliasOf> = record
+                                    .header(WarcHeader::PayloadDigest)
+                                    .map(|digest| AliasOf::PayloadDigest(digest.to_string()))
+                                    .or_else(|| {
+                                        record
+                                            .header(WarcHeader::Unknown(REFERS_TO_TARGET_URI.to_string()))
+                                            .and_then(|uri| canonical_url::canonicalise_str(&uri))
+                                            .map(AliasOf::Url)
+                                    });
+                                if
+                                    let (Some(url), Some(original)) = (
+                                        canonical_url::canonicalise_str(&target_uri),
+                                        original,
+                                    )
+                                {
+                                    batch.aliases.push(WebsiteAlias { url, original });
+                                }
+                            }
+                            // Extracted text from WET files, indexed without parsing HTML
+                            "conversion" if kind == InputKind::Wet => {
                                 matching_count += 1;
-                                if webpage.is_some() {
-                                    let webpage: webpage::Webpage = webpage.unwrap();
-                                    if webpage.text_body.is_some() {
-                                        results.push(webpage);
+                                if let Some(webpage) = wet::parse_conversion(&record) {
+                                    valid_count += 1;
+                                    batch.webpages.push(webpage);
+                                }
+                            }
+                            // Titles, meta tags and links from WAT files
+                            "metadata" if kind == InputKind::Wat => {
+                                if let Some(webpage) = wat::parse_metadata(&record) {
+                                    matching_count += 1;
+                                    valid_count += 1;
+                                    if let Some(source) = webpage.description_source {
+                                        *description_source_counts
+                                            .entry(source.as_str().to_string())
+                                            .or_insert(0) += 1;
                                     }
-                                    // if results.len() > 20 {
-                                    //     break;
-                                    // }
+                                    batch.webpages.push(webpage);
                                 }
                             }
-                            let to_increase: u64 = (count as u64) - progress_bar.position();
-                            progress_bar.inc(to_increase);
+                            // Requests, other metadata and other conversions have nothing to index
+                            _ => {}
                         }
+                        let to_increase: u64 = (count as u64) - progress_bar.position();
+                        progress_bar.inc(to_increase);
                     }
-                    None => {}
+                }
+
+                // Hand over full batches, waiting here whenever the writer falls behind
+                if batch.len() >= WEBPAGE_BATCH_SIZE {
+                    let mut full_batch: WebpageBatch = std::mem::take(&mut batch);
+                    let stored: usize = runtime.block_on(alias_stored_payloads(&mut full_batch, database))?;
+                    duplicate_count += stored;
+                    matching_count -= stored as i32;
+                    valid_count -= stored;
+                    sender.blocking_send(full_batch).map_err(|_| "Database writer stopped early")?;
                 }
                 count += 1;
                 if count % 1000 == 0 {
@@ -155,18 +397,82 @@ pub async fn read_warc_file(
             }
         }
     }
+    if !batch.is_empty() {
+        let stored: usize = runtime.block_on(alias_stored_payloads(&mut batch, database))?;
+        duplicate_count += stored;
+        matching_count -= stored as i32;
+        valid_count -= stored;
+        sender.blocking_send(batch).map_err(|_| "Database writer stopped early")?;
+    }
     let duration: Duration = time_taken.elapsed();
 
     let msg: String = format!(
-        "{} | {} | {} | {}",
+        "{} | {} | {} | {} | {} | {} | {} | {} | {} | {}",
         format!("Finished reading {}", file_number).green().bold(),
         format!("Time taken overall: {:.2} s", duration.as_secs_f64()).cyan(),
         format!("Matching websites: {}/{}", matching_count, count).yellow(),
-        format!("Valid websites: {}/{}", results.len(), matching_count).yellow()
+        format!("Valid websites: {}/{}", valid_count, matching_count).yellow(),
+        format!("Record types: {}", summarise_counts(record_type_counts)).yellow(),
+        format!("Status codes: {}", summarise_counts(status_counts)).yellow(),
+        format!("Duplicate payloads: {}", duplicate_count).yellow(),
+        format!("Already ingested: {}", resumed_count).yellow(),
+        format!("Charsets: {}", summarise_counts(charset_counts)).yellow(),
+        format!("Descriptions: {}", summarise_counts(description_source_counts)).yellow()
     );
 
     progress_bar.println(msg);
     progress_bar.finish_and_clear();
 
-    Ok(results)
+    Ok(())
+}
+
+// Replace the webpages whose payload is already stored by an alias of the stored page,
+// with one lookup for the whole batch. Returns how many webpages were replaced.
+async fn alias_stored_payloads(
+    batch: &mut WebpageBatch,
+    database: &Database
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let digests: Vec<String> = batch.webpages
+        .iter()
+        .filter_map(|webpage| webpage.payload_digest.clone())
+        .collect();
+    if digests.is_empty() {
+        return Ok(0);
+    }
+    let stored_digests: HashSet<String> = database::stored_payload_digests(database, &digests).await?;
+    if stored_digests.is_empty() {
+        return Ok(0);
+    }
+
+    let webpage_count: usize = batch.webpages.len();
+    let mut webpages: Vec<webpage::Webpage> = Vec::with_capacity(webpage_count);
+    for webpage in batch.webpages.drain(..) {
+        match &webpage.payload_digest {
+            Some(digest) if stored_digests.contains(digest) => {
+                let url: Option<String> = webpage.warc_target_uri
+                    .as_deref()
+                    .and_then(canonical_url::canonicalise_str);
+                if let Some(url) = url {
+                    batch.aliases.push(WebsiteAlias {
+                        url,
+                        original: AliasOf::PayloadDigest(digest.clone()),
+                    });
+                }
+            }
+            _ => webpages.push(webpage),
+        }
+    }
+    batch.webpages = webpages;
+    Ok(webpage_count - batch.webpages.len())
+}
+
+// List counts with the most common first, e.g. "UTF-8: 900, windows-1252: 50"
+fn summarise_counts(counts: HashMap<String, usize>) -> String {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+        .iter()
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
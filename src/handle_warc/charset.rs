@@ -0,0 +1,54 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{ Encoding, UTF_8, UTF_16BE, UTF_16LE };
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// Number of bytes scanned for a `<meta charset>` declaration, as in the HTML spec prescan
+const META_PRESCAN_LENGTH: usize = 1024;
+
+static META_CHARSET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)<meta[^>]+charset\s*=\s*["']?\s*([a-z0-9_:.\-]+)"#).unwrap()
+});
+
+// Decode an HTML payload to UTF-8, returning the text and the charset it was decoded from.
+// The charset comes from the HTTP `Content-Type`, then `<meta charset>`, then byte-level sniffing.
+pub fn decode_html(body: &[u8], content_type: Option<&str>) -> (String, &'static Encoding) {
+    let encoding: &'static Encoding = content_type
+        .and_then(charset_from_content_type)
+        .or_else(|| charset_from_meta(body))
+        .unwrap_or_else(|| sniff_charset(body));
+
+    // A byte order mark still takes precedence over any declared charset
+    let (text, encoding_used, _) = encoding.decode(body);
+    (text.into_owned(), encoding_used)
+}
+
+fn charset_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+}
+
+fn charset_from_meta(body: &[u8]) -> Option<&'static Encoding> {
+    let prescan: std::borrow::Cow<str> = String::from_utf8_lossy(
+        &body[..body.len().min(META_PRESCAN_LENGTH)]
+    );
+    let label: &str = META_CHARSET.captures(&prescan)?.get(1)?.as_str();
+    let encoding: &'static Encoding = Encoding::for_label(label.as_bytes())?;
+
+    // A page that could be read as ASCII can't really be UTF-16, so the declaration means UTF-8
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        Some(UTF_8)
+    } else {
+        Some(encoding)
+    }
+}
+
+fn sniff_charset(body: &[u8]) -> &'static Encoding {
+    let mut detector: EncodingDetector = EncodingDetector::new();
+    detector.feed(body, true);
+    detector.guess(None, true)
+}
@@ -0,0 +1,454 @@
+use reqwest::{ Client, Response, StatusCode };
+use reqwest::header::{ CONTENT_LENGTH, CONTENT_RANGE, RANGE };
+use rand::{ thread_rng, Rng };
+use sha2::{ Digest, Sha256 };
+use std::env;
+use std::error::Error;
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+use std::fs::create_dir_all;
+use std::sync::Arc;
+use indicatif::{ MultiProgress, ProgressBar, ProgressStyle };
+use tokio::fs::{ File, OpenOptions };
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use colored::*;
+use url::Url;
+
+use crate::handle_warc::archive_source::ArchiveSource;
+
+const MAX_DOWNLOAD_ATTEMPTS: u32 = 5;
+
+pub async fn download_warc_file(
+    source: &ArchiveSource,
+    file_name: &str,
+    multibar: &Arc<MultiProgress>
+) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    // Create the URL
+    let url: Url = source.url_for(file_name)?;
+
+    // Initialize HTTP client
+    let client: Client = Client::new();
+
+    // Create the directory for storing the files
+    let dir_path: &Path = Path::new("warc_files");
+    create_dir_all(dir_path)?;
+
+    // Extract the filename from the file path
+    let path: &Path = Path::new(file_name);
+    let file_name: std::borrow::Cow<str> = path
+        .file_name()
+        .ok_or("Invalid file name")?
+        .to_string_lossy();
+
+    // Create file paths, the download goes to a partial file until it is complete
+    let file_path: PathBuf = dir_path.join(file_name.to_string()).with_extension("gz");
+    let partial_path: PathBuf = file_path.with_extension("gz.part");
+
+    let parts: Vec<&str> = file_name.split('-').collect();
+    let file_number: Vec<&str> = parts[parts.len() - 1].split('.').collect();
+    let file_number: &str = file_number[0];
+
+    let progress_bar: ProgressBar = multibar.add(ProgressBar::new(0));
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                &format!(
+                    "Downloading {}: [{{elapsed_precise}}] [{{wide_bar:40.cyan/blue}}] {{bytes}}/{{total_bytes}} | Speed: {{bytes_per_sec}} | Time Left: {{eta}}",
+                    file_number.to_string().green().bold()
+                )
+            )
+            .unwrap()
+            .progress_chars("#>-")
+    );
+
+    let backoff_delay = Duration::from_millis(thread_rng().gen_range(500..1500)); // Random initial delay between 500ms and 1500ms
+    let mut attempt: u32 = 0;
+
+    loop {
+        match download_to_partial_file(source, &client, &url, &partial_path, &progress_bar).await {
+            Ok(()) => {
+                break;
+            }
+            Err(err) => {
+                attempt += 1;
+                if attempt >= MAX_DOWNLOAD_ATTEMPTS || !is_retryable(err.as_ref()) {
+                    progress_bar.finish_and_clear();
+                    return Err(err);
+                }
+                progress_bar.println(
+                    format!(
+                        "{} {} (attempt {}/{}): {}",
+                        "Retrying download of".yellow().bold(),
+                        file_number,
+                        attempt,
+                        MAX_DOWNLOAD_ATTEMPTS,
+                        err
+                    )
+                );
+                tokio::time::sleep(backoff_delay * 2u32.pow(attempt - 1)).await;
+            }
+        }
+    }
+
+    // Optionally verify the file against a digest published next to it
+    if let Ok(digest_suffix) = env::var("WARC_DIGEST_SUFFIX") {
+        let digest_url: Url = Url::parse(&format!("{}{}", url, digest_suffix))?;
+        let expected_digest: String = fetch_published_digest(source, &client, &digest_url).await?;
+        let actual_digest: String = sha256_file(&partial_path).await?;
+        if actual_digest != expected_digest {
+            // A corrupt file can't be resumed, so start from scratch next time
+            tokio::fs::remove_file(&partial_path).await?;
+            progress_bar.finish_and_clear();
+            return Err(
+                Box::new(DownloadError::DigestMismatch {
+                    expected: expected_digest,
+                    actual: actual_digest,
+                })
+            );
+        }
+    }
+
+    tokio::fs::rename(&partial_path, &file_path).await?;
+
+    progress_bar.println(
+        format!("{}{}", "Downloaded file to: ".green().bold(), file_path.to_string_lossy().blue())
+    );
+    progress_bar.finish_and_clear();
+    // Return the path to the extracted file
+    Ok(file_path)
+}
+
+// Download the remaining part of a file, resuming from whatever is already on disk
+async fn download_to_partial_file(
+    source: &ArchiveSource,
+    client: &Client,
+    url: &Url,
+    partial_path: &Path,
+    progress_bar: &ProgressBar
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let existing_length: u64 = match tokio::fs::metadata(partial_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    let mut request: reqwest::RequestBuilder = source.get(client, url)?;
+    if existing_length > 0 {
+        request = request.header(RANGE, format!("bytes={}-", existing_length));
+    }
+    let response: Response = request.send().await?;
+
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // A partial file that already has every byte was complete before the last attempt stopped
+        if parse_unsatisfied_range(&response) == Some(existing_length) {
+            progress_bar.set_length(existing_length);
+            progress_bar.set_position(existing_length);
+            return Ok(());
+        }
+        // Otherwise the partial file doesn't match the remote file, so start again
+        tokio::fs::remove_file(partial_path).await?;
+        return Err(Box::new(DownloadError::InvalidPartialFile(existing_length)));
+    }
+    let mut response: Response = response.error_for_status()?;
+
+    // Servers that ignore the range request send the whole file again
+    let (offset, total_length): (u64, u64) = if response.status() == StatusCode::PARTIAL_CONTENT {
+        parse_content_range(&response).ok_or(DownloadError::UnknownLength)?
+    } else {
+        (0, parse_content_length(&response).ok_or(DownloadError::UnknownLength)?)
+    };
+
+    let mut output_file: File = if offset == 0 {
+        File::create(partial_path).await?
+    } else if offset == existing_length {
+        OpenOptions::new().append(true).open(partial_path).await?
+    } else {
+        tokio::fs::remove_file(partial_path).await?;
+        return Err(Box::new(DownloadError::InvalidPartialFile(existing_length)));
+    };
+
+    progress_bar.set_length(total_length);
+    progress_bar.set_position(offset);
+
+    let mut written: u64 = offset;
+    while let Some(chunk) = response.chunk().await? {
+        output_file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        progress_bar.set_position(written);
+    }
+    output_file.flush().await?;
+
+    if written != total_length {
+        return Err(
+            Box::new(DownloadError::SizeMismatch {
+                expected: total_length,
+                actual: written,
+            })
+        );
+    }
+    Ok(())
+}
+
+// Parse a `Content-Range: bytes start-end/total` header
+fn parse_content_range(response: &Response) -> Option<(u64, u64)> {
+    let content_range: &str = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = content_range.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()?))
+}
+
+// Parse the `Content-Range: bytes */total` header of a 416 response
+fn parse_unsatisfied_range(response: &Response) -> Option<u64> {
+    let content_range: &str = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    content_range.strip_prefix("bytes */")?.trim().parse().ok()
+}
+
+fn parse_content_length(response: &Response) -> Option<u64> {
+    response.headers().get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+// Fetch a published digest, taking the first hex token so `sha256sum` output works as-is
+async fn fetch_published_digest(
+    source: &ArchiveSource,
+    client: &Client,
+    digest_url: &Url
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let body: String = source.get(client, digest_url)?.send().await?.error_for_status()?.text().await?;
+    let digest: &str = body.split_whitespace().next().ok_or(DownloadError::MissingDigest)?;
+    Ok(digest.to_lowercase())
+}
+
+async fn sha256_file(file_path: &Path) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let mut file: File = File::open(file_path).await?;
+    let mut hasher: Sha256 = Sha256::new();
+    let mut buffer: Vec<u8> = vec![0; 1 << 16];
+
+    loop {
+        let read: usize = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// Network failures, server errors and truncated downloads are worth retrying
+fn is_retryable(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    if let Some(download_error) = err.downcast_ref::<DownloadError>() {
+        return matches!(
+            download_error,
+            DownloadError::SizeMismatch { .. } | DownloadError::InvalidPartialFile(_)
+        );
+    }
+    if let Some(reqwest_error) = err.downcast_ref::<reqwest::Error>() {
+        return !reqwest_error.status().is_some_and(|status| status.is_client_error());
+    }
+    true
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    UnknownLength,
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    InvalidPartialFile(u64),
+    MissingDigest,
+    DigestMismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::UnknownLength => write!(f, "Server did not report the file length"),
+            DownloadError::SizeMismatch { expected, actual } =>
+                write!(f, "Downloaded {} bytes but expected {}", actual, expected),
+            DownloadError::InvalidPartialFile(length) =>
+                write!(f, "Partial download of {} bytes could not be resumed", length),
+            DownloadError::MissingDigest => write!(f, "Published digest is empty"),
+            DownloadError::DigestMismatch { expected, actual } =>
+                write!(f, "Digest mismatch: expected {} but got {}", expected, actual),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    // A canned response, sent whole or cut off partway through its body
+    struct StandIn {
+        head: String,
+        body: &'static [u8],
+        truncate_at: Option<usize>,
+    }
+
+    impl StandIn {
+        fn new(status: &str, headers: &[(&str, String)], body: &'static [u8]) -> Self {
+            let mut head: String = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+            for (name, value) in headers {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            head.push_str("\r\n");
+            StandIn { head, body, truncate_at: None }
+        }
+
+        fn truncated_at(mut self, length: usize) -> Self {
+            self.truncate_at = Some(length);
+            self
+        }
+    }
+
+    // Serve each response to one connection in turn, returning the Range header of each request
+    async fn serve(responses: Vec<StandIn>) -> (ArchiveSource, Url, JoinHandle<Vec<Option<String>>>) {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url: Url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let url: Url = base_url.join("crawl-data/segment/warc/file.warc.gz").unwrap();
+
+        let server = tokio::spawn(async move {
+            let mut ranges: Vec<Option<String>> = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = stream.split();
+                let mut reader = tokio::io::BufReader::new(reader);
+
+                let mut range: Option<String> = None;
+                loop {
+                    let mut line: String = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("range") {
+                            range = Some(value.trim().to_string());
+                        }
+                    }
+                }
+                ranges.push(range);
+
+                let sent: &[u8] = &response.body[..response.truncate_at.unwrap_or(response.body.len())];
+                writer.write_all(response.head.as_bytes()).await.unwrap();
+                writer.write_all(sent).await.unwrap();
+                writer.flush().await.unwrap();
+                if response.truncate_at.is_some() {
+                    // Give the client time to read what was sent before the connection drops
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+            ranges
+        });
+        (ArchiveSource::Http { base_url }, url, server)
+    }
+
+    fn partial_path(name: &str) -> PathBuf {
+        let path: PathBuf = env::temp_dir().join(format!("download-test-{}-{}.gz.part", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    async fn download(source: &ArchiveSource, url: &Url, path: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+        download_to_partial_file(source, &Client::new(), url, path, &ProgressBar::hidden()).await
+    }
+
+    #[tokio::test]
+    async fn resumes_a_truncated_download_with_a_range_request() {
+        let (source, url, server) = serve(
+            vec![
+                StandIn::new("200 OK", &[("Content-Length", "11".to_string())], b"hello world").truncated_at(6),
+                StandIn::new(
+                    "206 Partial Content",
+                    &[
+                        ("Content-Length", "5".to_string()),
+                        ("Content-Range", "bytes 6-10/11".to_string()),
+                    ],
+                    b"world"
+                )
+            ]
+        ).await;
+        let path: PathBuf = partial_path("resume");
+
+        assert!(download(&source, &url, &path).await.is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello ");
+
+        download(&source, &url, &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        assert_eq!(server.await.unwrap(), vec![None, Some("bytes=6-".to_string())]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn short_body_is_a_size_mismatch() {
+        let (source, url, _server) = serve(
+            vec![
+                StandIn::new(
+                    "206 Partial Content",
+                    &[
+                        ("Content-Length", "5".to_string()),
+                        ("Content-Range", "bytes 6-10/20".to_string()),
+                    ],
+                    b"world"
+                )
+            ]
+        ).await;
+        let path: PathBuf = partial_path("short");
+        std::fs::write(&path, b"hello ").unwrap();
+
+        let err = download(&source, &url, &path).await.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<DownloadError>(),
+                Some(DownloadError::SizeMismatch { expected: 20, actual: 11 })
+            )
+        );
+        assert!(is_retryable(err.as_ref()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_length_is_unknown_length() {
+        let (source, url, _server) = serve(
+            vec![StandIn::new("200 OK", &[("Transfer-Encoding", "chunked".to_string())], b"5\r\nhello\r\n0\r\n\r\n")]
+        ).await;
+        let path: PathBuf = partial_path("unknown");
+
+        let err = download(&source, &url, &path).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::UnknownLength)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_range_on_a_complete_file_finishes_the_download() {
+        let (source, url, _server) = serve(
+            vec![StandIn::new("416 Range Not Satisfiable", &[("Content-Range", "bytes */11".to_string())], b"")]
+        ).await;
+        let path: PathBuf = partial_path("complete");
+        std::fs::write(&path, b"hello world").unwrap();
+
+        download(&source, &url, &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello world");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_range_on_a_mismatched_file_starts_again() {
+        let (source, url, _server) = serve(
+            vec![StandIn::new("416 Range Not Satisfiable", &[("Content-Range", "bytes */5".to_string())], b"")]
+        ).await;
+        let path: PathBuf = partial_path("mismatched");
+        std::fs::write(&path, b"hello world").unwrap();
+
+        let err = download(&source, &url, &path).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DownloadError>(), Some(DownloadError::InvalidPartialFile(11))));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,191 @@
+use flate2::read::{ GzDecoder, ZlibDecoder, DeflateDecoder };
+use std::io::Read;
+
+use crate::handle_warc::webpage::ParseError;
+
+// Largest body a compressed payload may decode to. Records are truncated well below this,
+// so larger output is a decompression bomb.
+const MAX_DECODED_BYTES: u64 = 16 * 1024 * 1024;
+
+// An HTTP/1.x response as stored in the body of a WARC response record
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status_code: Option<usize>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn parse(raw: &[u8]) -> Result<Self, ParseError> {
+        // The header block ends at the first blank line, the payload is everything after it
+        let (header_block, payload): (&[u8], &[u8]) = split_at_blank_line(raw).ok_or_else(||
+            ParseError::MalformedHttpResponse("missing end of headers".to_string())
+        )?;
+        let header_block: String = String::from_utf8_lossy(header_block).to_string();
+        let mut lines = header_block.lines();
+
+        let status_line: &str = lines
+            .next()
+            .ok_or_else(|| ParseError::MalformedHttpResponse("missing status line".to_string()))?;
+        if !status_line.starts_with("HTTP/") {
+            return Err(
+                ParseError::MalformedHttpResponse(format!("invalid status line: {}", status_line))
+            );
+        }
+        let status_code: Option<usize> = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok());
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in lines {
+            // Obsolete line folding continues the previous header value
+            if line.starts_with(' ') || line.starts_with('\t') {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        let mut response: HttpResponse = HttpResponse {
+            status_code,
+            headers,
+            body: payload.to_vec(),
+        };
+        response.decode_body()?;
+        Ok(response)
+    }
+
+    // Get the first value of a header, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Undo transfer and content encodings. A payload that can't be decompressed is rejected,
+    // as its raw bytes would only be indexed as garbage.
+    fn decode_body(&mut self) -> Result<(), ParseError> {
+        let transfer_encoding: String = self.header("Transfer-Encoding").unwrap_or_default().to_lowercase();
+        if transfer_encoding.contains("chunked") {
+            if let Some(body) = decode_chunked(&self.body) {
+                self.body = body;
+            }
+        }
+
+        let content_encoding: String = self.header("Content-Encoding").unwrap_or_default().to_lowercase();
+        let decoded: Result<Vec<u8>, String> = match content_encoding.trim() {
+            "gzip" | "x-gzip" => read_all(GzDecoder::new(self.body.as_slice())),
+            // Some servers send raw deflate streams without the zlib wrapper
+            "deflate" =>
+                read_all(ZlibDecoder::new(self.body.as_slice())).or_else(|_|
+                    read_all(DeflateDecoder::new(self.body.as_slice()))
+                ),
+            "br" => read_all(brotli::Decompressor::new(self.body.as_slice(), 4096)),
+            // Identity and unknown encodings are kept as they are
+            _ => {
+                return Ok(());
+            }
+        };
+        self.body = decoded.map_err(|err|
+            ParseError::UndecodableBody(format!("{}: {}", content_encoding.trim(), err))
+        )?;
+        Ok(())
+    }
+}
+
+fn split_at_blank_line(raw: &[u8]) -> Option<(&[u8], &[u8])> {
+    for i in 0..raw.len() {
+        if raw[i..].starts_with(b"\r\n\r\n") {
+            return Some((&raw[..i], &raw[i + 4..]));
+        }
+        if raw[i..].starts_with(b"\n\n") {
+            return Some((&raw[..i], &raw[i + 2..]));
+        }
+    }
+    None
+}
+
+// Decode a `Transfer-Encoding: chunked` payload, returning None if it is malformed
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded: Vec<u8> = Vec::with_capacity(body.len());
+
+    loop {
+        let line_end: usize = body.iter().position(|&byte| byte == b'\n')?;
+        let size_line: String = String::from_utf8_lossy(&body[..line_end]).to_string();
+        // Chunk extensions after `;` are ignored
+        let size_hex: &str = size_line.split(';').next().unwrap_or_default().trim();
+        let size: usize = usize::from_str_radix(size_hex, 16).ok()?;
+        body = &body[line_end + 1..];
+
+        if size == 0 {
+            return Some(decoded);
+        }
+        if body.len() < size {
+            return None;
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size..];
+        body = body.strip_prefix(b"\r\n").or_else(|| body.strip_prefix(b"\n")).unwrap_or(body);
+    }
+}
+
+// Read the whole decoded output, giving up once it grows past `MAX_DECODED_BYTES`
+fn read_all<R: Read>(reader: R) -> Result<Vec<u8>, String> {
+    let mut decoded: Vec<u8> = Vec::new();
+    reader
+        .take(MAX_DECODED_BYTES + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|err| err.to_string())?;
+    if (decoded.len() as u64) > MAX_DECODED_BYTES {
+        return Err(format!("decoded body is larger than {} bytes", MAX_DECODED_BYTES));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder: GzEncoder<Vec<u8>> = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn gzipped_response(body: &[u8]) -> Vec<u8> {
+        let mut raw: Vec<u8> = b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n".to_vec();
+        raw.extend_from_slice(body);
+        raw
+    }
+
+    #[test]
+    fn decodes_gzip_payloads() {
+        // Repetitive pages compress far better than 20x and are still decoded
+        let html: Vec<u8> = "<p>same paragraph</p>".repeat(10_000).into_bytes();
+        let response: HttpResponse = HttpResponse::parse(&gzipped_response(&gzip(&html))).unwrap();
+        assert_eq!(response.body, html);
+    }
+
+    #[test]
+    fn rejects_payloads_that_fail_to_decode() {
+        let result = HttpResponse::parse(&gzipped_response(b"not gzip at all"));
+        assert!(matches!(result, Err(ParseError::UndecodableBody(_))));
+    }
+
+    #[test]
+    fn rejects_payloads_that_decode_past_the_limit() {
+        let bomb: Vec<u8> = gzip(&vec![0; (MAX_DECODED_BYTES as usize) + 1]);
+        let result = HttpResponse::parse(&gzipped_response(&bomb));
+        assert!(matches!(result, Err(ParseError::UndecodableBody(_))));
+    }
+}
@@ -0,0 +1,72 @@
+use markup5ever_rcdom::RcDom;
+
+use crate::helper_functions;
+
+// Link relations that mark a link as not editorially endorsed
+const NOFOLLOW_RELS: [&str; 3] = ["nofollow", "ugc", "sponsored"];
+
+// Page-level directives from `<meta name="robots">` and `X-Robots-Tag` headers
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RobotsDirectives {
+    pub noindex: bool,
+    pub nofollow: bool,
+}
+
+impl RobotsDirectives {
+    pub fn from_page(dom: &RcDom, headers: &[(String, String)]) -> Self {
+        let meta_content: Option<String> = helper_functions::extract_robots_meta_from_html(&dom.document);
+        RobotsDirectives::from_parts(meta_content.as_deref(), headers)
+    }
+
+    // Combine the content of a robots meta tag with the response headers
+    pub fn from_parts(meta_content: Option<&str>, headers: &[(String, String)]) -> Self {
+        let mut directives: RobotsDirectives = RobotsDirectives::default();
+
+        if let Some(content) = meta_content {
+            directives.apply(content);
+        }
+        for (name, value) in headers {
+            if name.eq_ignore_ascii_case("X-Robots-Tag") {
+                directives.apply_header(value);
+            }
+        }
+        directives
+    }
+
+    // Apply a comma separated list such as `noindex, nofollow`
+    fn apply(&mut self, directives: &str) {
+        for directive in directives.split(',') {
+            match directive.trim().to_lowercase().as_str() {
+                "noindex" => {
+                    self.noindex = true;
+                }
+                "nofollow" => {
+                    self.nofollow = true;
+                }
+                "none" => {
+                    self.noindex = true;
+                    self.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Header values can be scoped to a crawler, as in `googlebot: noindex`.
+    // Only directives for every crawler apply here.
+    fn apply_header(&mut self, value: &str) {
+        match value.split_once(':') {
+            Some((user_agent, directives)) if !user_agent.contains(',') => {
+                if user_agent.trim() == "*" {
+                    self.apply(directives);
+                }
+            }
+            _ => self.apply(value),
+        }
+    }
+}
+
+// Whether a link's `rel` attribute asks crawlers not to follow it
+pub fn is_nofollow_rel(rel: Option<&str>) -> bool {
+    rel.is_some_and(|rel| rel.split_whitespace().any(|value| NOFOLLOW_RELS.contains(&value)))
+}
@@ -0,0 +1,169 @@
+use serde_json::Value;
+use url::Url;
+use warc::{ WarcHeader, Record, BufferedBody };
+use whichlang::{ detect_language, Lang };
+
+use crate::analyzer::{ self, Analyzer };
+use crate::canonical_url;
+use crate::handle_warc::robots::{ self, RobotsDirectives };
+use crate::handle_warc::webpage::{ analyze_field, FieldTerms, Webpage };
+use crate::helper_functions::{ self, DescriptionSource, Link };
+
+// Build a webpage from the WAT metadata of an HTML response.
+// WAT files have no page text, so only the title, description, URL and links are indexed.
+pub fn parse_metadata(record: &Record<BufferedBody>) -> Option<Webpage> {
+    let json: Value = serde_json::from_slice(record.body()).ok()?;
+    let envelope: &Value = &json["Envelope"];
+    let warc_header: &Value = &envelope["WARC-Header-Metadata"];
+    let response: &Value = &envelope["Payload-Metadata"]["HTTP-Response-Metadata"];
+
+    // Request and metadata records are described in the same files
+    if warc_header["WARC-Type"].as_str()? != "response" {
+        return None;
+    }
+    let status_code: usize = response["Response-Message"]["Status"].as_str()?.parse().ok()?;
+    if !(200..300).contains(&status_code) {
+        return None;
+    }
+
+    let headers: Vec<(String, String)> = response["Headers"]
+        .as_object()
+        .map(|headers| {
+            headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+                .collect()
+        })
+        .unwrap_or_default();
+    let content_type: String = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Type"))
+        .map(|(_, value)| value.clone())?;
+    if !content_type.contains("text/html") {
+        return None;
+    }
+
+    let html: &Value = &response["HTML-Metadata"];
+    let metas: &[Value] = html["Head"]["Metas"].as_array().map_or(&[], |metas| metas.as_slice());
+    let directives: RobotsDirectives = RobotsDirectives::from_parts(
+        meta_content(metas, "name", "robots").as_deref(),
+        &headers
+    );
+    if directives.noindex {
+        return None;
+    }
+
+    let warc_target_uri: String = warc_header["WARC-Target-URI"].as_str()?.to_string();
+    let target: Url = Url::parse(&warc_target_uri).ok()?;
+    let canonical_link: Option<Url> = html["Head"]["Link"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|link| {
+            link["rel"]
+                .as_str()
+                .is_some_and(|rel| rel.split_whitespace().any(|rel| rel.eq_ignore_ascii_case("canonical")))
+        })
+        .and_then(|link| target.join(link["url"].as_str()?.trim()).ok());
+    let canonical_url: String = canonical_url::page_url(&target, canonical_link);
+
+    let title: Option<String> = html["Head"]["Title"]
+        .as_str()
+        .map(|title| title.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|title| !title.is_empty());
+    let (description, description_source): (Option<String>, Option<DescriptionSource>) = [
+        ("name", "description", DescriptionSource::Meta),
+        ("property", "og:description", DescriptionSource::OpenGraph),
+        ("name", "twitter:description", DescriptionSource::Twitter),
+    ]
+        .into_iter()
+        .find_map(|(attr_name, attr_value, source)| {
+            meta_content(metas, attr_name, attr_value).map(|description| (Some(description), Some(source)))
+        })
+        .unwrap_or((None, None));
+
+    // The language can only be guessed from the title and description
+    let lang_text: String = format!(
+        "{} {}",
+        title.as_deref().unwrap_or_default(),
+        description.as_deref().unwrap_or_default()
+    );
+    if lang_text.trim().is_empty() {
+        return None;
+    }
+    let lang: Lang = detect_language(&lang_text);
+    if !analyzer::is_accepted_language(lang) {
+        return None;
+    }
+    let analyzer: &Analyzer = analyzer::analyzer_for(lang);
+
+    let links: Vec<Link> = html["Links"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|link| link["path"].as_str() == Some("A@/href"))
+        .filter_map(|link| {
+            let url: Url = target.join(link["url"].as_str()?.trim()).ok()?;
+            let anchor_text: String = link["text"]
+                .as_str()
+                .unwrap_or_default()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let title: Option<String> = link["title"].as_str().map(|title| title.trim().to_string());
+            let rel: Option<String> = link["rel"].as_str().map(|rel| rel.to_lowercase());
+            Some(Link {
+                url: canonical_url::canonicalise(&url).to_string(),
+                anchor_terms: analyze_field(
+                    analyzer,
+                    &format!("{} {}", anchor_text, title.as_deref().unwrap_or_default())
+                ),
+                nofollow: directives.nofollow || robots::is_nofollow_rel(rel.as_deref()),
+                anchor_text,
+                title,
+                rel,
+            })
+        })
+        .collect();
+
+    let field_terms: FieldTerms = FieldTerms {
+        title: analyze_field(analyzer, title.as_deref().unwrap_or_default()),
+        description: analyze_field(analyzer, description.as_deref().unwrap_or_default()),
+        headings: Vec::new(),
+        url: analyze_field(analyzer, &helper_functions::url_to_words(&canonical_url)),
+    };
+
+    Some(Webpage {
+        warc_date: warc_header["WARC-Date"].as_str().map(|date| date.to_string()),
+        // The metadata record's own ID, as that is the record read from this file
+        record_id: record.header(WarcHeader::RecordID).map(|record_id| record_id.to_string()),
+        warc_target_uri: Some(warc_target_uri),
+        canonical_url: Some(canonical_url),
+        warc_identified_payload_type: warc_header["WARC-Identified-Payload-Type"]
+            .as_str()
+            .map(|payload_type| payload_type.to_string()),
+        status_code: Some(status_code),
+        content_type: Some(content_type),
+        http_headers: Some(headers),
+        title,
+        description,
+        description_source,
+        links: if links.is_empty() { None } else { Some(links) },
+        lang: Some(lang),
+        field_terms: Some(field_terms),
+        // The payload digest describes the response, which wasn't parsed here, so it isn't stored
+        ..Default::default()
+    })
+}
+
+// The non-empty content of the first meta tag whose attribute matches, ignoring case
+fn meta_content(metas: &[Value], attr_name: &str, attr_value: &str) -> Option<String> {
+    metas
+        .iter()
+        .filter(|meta| {
+            meta[attr_name].as_str().is_some_and(|value| value.trim().eq_ignore_ascii_case(attr_value))
+        })
+        .filter_map(|meta| meta["content"].as_str())
+        .map(|content| content.split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|content| !content.is_empty())
+}
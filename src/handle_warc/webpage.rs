@@ -3,71 +3,99 @@ use html5ever::parse_document;
 use html5ever::tendril::TendrilSink;
 use markup5ever_rcdom::RcDom;
 use whichlang::{ detect_language, Lang };
-use std::str::from_utf8;
+use encoding_rs::Encoding;
+use url::Url;
 
-use crate::helper_functions;
+use crate::analyzer::{ self, Analyzer };
+use crate::canonical_url;
+use crate::handle_warc::charset;
+use crate::handle_warc::http_response::HttpResponse;
+use crate::handle_warc::robots::{ self, RobotsDirectives };
+use crate::helper_functions::{ self, DescriptionSource, Link };
+use crate::main_content;
+use crate::simhash;
+use crate::tokenizer::Token;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Webpage {
     pub warc_date: Option<String>,
+    // WARC-Record-ID of the record the page was read from, used to resume a file
+    pub record_id: Option<String>,
     pub warc_target_uri: Option<String>,
+    // The URL the page is stored under, see `canonical_page_url`
+    pub canonical_url: Option<String>,
     pub warc_identified_payload_type: Option<String>,
+    pub payload_digest: Option<String>,
     pub status_code: Option<usize>,
     pub content_type: Option<String>,
+    // Content-Length of the WARC record
     pub content_length: Option<usize>,
-    pub html_body: Option<String>,
+    // Every header of the HTTP response, in the order they were sent
+    pub http_headers: Option<Vec<(String, String)>>,
+    pub charset: Option<String>,
     pub title: Option<String>,
     pub description: Option<String>,
-    pub links: Option<Vec<String>>,
+    pub description_source: Option<DescriptionSource>,
+    pub links: Option<Vec<Link>>,
+    // The main content of the page, None when too little was found to be trusted
     pub text_body: Option<String>,
+    // All visible text, including navigation and footers
+    pub full_text: Option<String>,
     pub lang: Option<Lang>,
-    pub lemmatised_text: Option<Vec<String>>,
+    pub lemmatised_text: Option<Vec<Token>>,
+    // SimHash of the analyzed body, used to find near-duplicate pages
+    pub simhash: Option<u64>,
+    pub field_terms: Option<FieldTerms>,
+}
+
+// Analyzed terms of the fields indexed separately from the body
+#[derive(Debug, Clone, Default)]
+pub struct FieldTerms {
+    pub title: Vec<String>,
+    pub description: Vec<String>,
+    pub headings: Vec<String>,
+    pub url: Vec<String>,
 }
 
 impl Webpage {
-    pub fn parse_record(record: &Record<BufferedBody>) -> Result<Option<Self>, ParseError> {
-        // Convert body bytes to UTF-8 string
-        let body: &str = from_utf8(record.body()).map_err(|e|
-            ParseError::BodyEncodingError(e.to_string())
-        )?;
-
-        let mut content_type: Option<String> = None;
-        let mut status_code: Option<usize> = None;
-        let mut html_body: String = String::new();
-        let mut header_processed: bool = false;
-
-        for line in body.lines() {
-            if line.starts_with("Content-Type") {
-                content_type = extract_content_type(line);
-                header_processed = true;
-                if
-                    content_type.as_ref().is_none() ||
-                    !content_type.as_ref().unwrap().contains("text/html")
-                {
-                    return Ok(None);
-                }
-            } else if line.starts_with("HTTP") {
-                status_code = extract_status(line);
-                header_processed = true;
-            } else if header_processed {
-                if line.contains("<html") || !html_body.is_empty() {
-                    html_body.push_str(line);
-                }
-            }
+    // Build a webpage from a response record and its parsed HTTP response
+    pub fn parse_response(
+        record: &Record<BufferedBody>,
+        response: HttpResponse
+    ) -> Result<Option<Self>, ParseError> {
+        let content_type: Option<String> = response.header("Content-Type").map(|s| s.to_string());
+        let status_code: Option<usize> = response.status_code;
+        if !content_type.as_ref().is_some_and(|content_type| content_type.contains("text/html")) {
+            return Ok(None);
         }
 
+        // Convert payload bytes to a UTF-8 string from whichever charset the page uses
+        let (html_body, encoding): (String, &'static Encoding) = charset::decode_html(
+            &response.body,
+            content_type.as_deref()
+        );
+
         match content_type.as_ref() {
-            Some(content_type) if content_type.contains("text/html") && !html_body.is_empty() => {
+            Some(content_type) if content_type.contains("text/html") && !html_body.trim().is_empty() => {
                 let parser: html5ever::Parser<RcDom> = parse_document(
                     RcDom::default(),
                     Default::default()
                 );
                 let dom: RcDom = parser.one(html_body.clone());
 
+                // Pages that ask not to be indexed are skipped
+                let directives: RobotsDirectives = RobotsDirectives::from_page(&dom, &response.headers);
+                if directives.noindex {
+                    return Ok(None);
+                }
+
                 let warc_date: Option<String> = record.header(WarcHeader::Date).map_or_else(
                     || None,
                     |date| Some(date.to_string())
                 );
+                let record_id: Option<String> = record
+                    .header(WarcHeader::RecordID)
+                    .map(|record_id| record_id.to_string());
                 let warc_target_uri: Option<String> = record
                     .header(WarcHeader::TargetURI)
                     .map_or_else(
@@ -81,47 +109,87 @@ impl Webpage {
                         |payload_type| Some(payload_type.to_string())
                     );
 
+                let canonical_url: Option<String> = warc_target_uri
+                    .as_deref()
+                    .and_then(|uri| canonical_page_url(&dom, uri));
+
+                let payload_digest: Option<String> = record
+                    .header(WarcHeader::PayloadDigest)
+                    .map(|digest| digest.to_string());
+
                 let content_length: Option<usize> = record
                     .header(WarcHeader::ContentLength)
-                    .map_or_else(
-                        || None,
-                        |content_length| Some(content_length.parse().unwrap())
-                    );
-                let mut lang: Option<Lang> = None;
-                let text_body: Option<String> = {
-                    let temp: String = extract_text_body(&dom).unwrap_or_default();
-                    if temp.is_empty() {
-                        None
-                    } else {
-                        lang = Some(detect_language(temp.as_str()));
-                        Some(temp)
+                    .and_then(|content_length| content_length.parse().ok());
+                let full_text: Option<String> = extract_text_body(&dom)
+                    .ok()
+                    .filter(|text| !text.is_empty());
+                let text_body: Option<String> = main_content
+                    ::extract_main_content(&dom)
+                    .filter(|text| !text.is_empty());
+                // Pages without enough main content are indexed from their full text instead
+                let indexed_text: Option<&str> = text_body.as_deref().or(full_text.as_deref());
+
+                let lang: Lang = match indexed_text.map(detect_language) {
+                    Some(lang) if analyzer::is_accepted_language(lang) => lang,
+                    _ => {
+                        return Ok(None);
                     }
                 };
 
-                if lang.is_none() || lang.unwrap() != Lang::Eng {
-                    return Ok(None);
-                }
+                let analyzer: &Analyzer = analyzer::analyzer_for(lang);
+                let lemmatised_text: Option<Vec<Token>> = indexed_text.map(|text| analyzer.analyze(text));
+                let simhash: Option<u64> = lemmatised_text
+                    .as_deref()
+                    .and_then(simhash::fingerprint);
 
-                let lemmatised_text: Option<Vec<String>> = match text_body.as_ref() {
-                    Some(text) => Some(helper_functions::lemmatise_string(&text.to_lowercase())),
-                    None => None,
+                let title: Option<String> = extract_title(&dom);
+                let (description, description_source): (Option<String>, Option<DescriptionSource>) = match
+                    extract_description(&dom, indexed_text.unwrap_or_default())
+                {
+                    Some((description, source)) => (Some(description), Some(source)),
+                    None => (None, None),
+                };
+                let headings: Vec<String> = helper_functions::extract_headings_from_html(&dom.document);
+                let field_terms: FieldTerms = FieldTerms {
+                    title: analyze_field(analyzer, title.as_deref().unwrap_or_default()),
+                    description: analyze_field(analyzer, description.as_deref().unwrap_or_default()),
+                    headings: analyze_field(analyzer, &headings.join("\n")),
+                    url: analyze_field(
+                        analyzer,
+                        &helper_functions::url_to_words(canonical_url.as_deref().unwrap_or_default())
+                    ),
                 };
 
                 // Generate the Webpage struct from the parsed data
                 let result: Webpage = Webpage {
                     warc_date,
+                    record_id,
                     warc_target_uri: warc_target_uri.clone(),
+                    canonical_url,
                     warc_identified_payload_type,
+                    payload_digest,
                     status_code,
                     content_type: Some(content_type.to_string()),
                     content_length,
-                    html_body: Some(html_body),
-                    title: extract_title(&dom),
-                    description: extract_description(&dom),
+                    http_headers: Some(response.headers),
+                    charset: Some(encoding.name().to_string()),
+                    title,
+                    description,
+                    description_source,
                     links: {
-                        let links: Vec<String> = helper_functions
+                        let mut links: Vec<Link> = helper_functions
                             ::extract_links_from_html(&dom, &warc_target_uri.unwrap_or_default())
                             .unwrap_or_default();
+                        for link in links.iter_mut() {
+                            let anchor: String = format!(
+                                "{} {}",
+                                link.anchor_text,
+                                link.title.as_deref().unwrap_or_default()
+                            );
+                            link.anchor_terms = analyze_field(analyzer, &anchor);
+                            link.nofollow =
+                                directives.nofollow || robots::is_nofollow_rel(link.rel.as_deref());
+                        }
                         if links.is_empty() {
                             None
                         } else {
@@ -129,8 +197,11 @@ impl Webpage {
                         }
                     },
                     text_body,
-                    lang,
+                    full_text,
+                    lang: Some(lang),
                     lemmatised_text,
+                    simhash,
+                    field_terms: Some(field_terms),
                 };
                 Ok(Some(result))
             }
@@ -139,6 +210,24 @@ impl Webpage {
     }
 }
 
+pub fn analyze_field(analyzer: &Analyzer, text: &str) -> Vec<String> {
+    analyzer
+        .analyze(text)
+        .into_iter()
+        .map(|token| token.text)
+        .collect()
+}
+
+// Canonicalise the target URI, taking the page's `<link rel="canonical">` into account
+fn canonical_page_url(dom: &RcDom, target_uri: &str) -> Option<String> {
+    let target: Url = Url::parse(target_uri).ok()?;
+    let canonical_link: Option<Url> = helper_functions::extract_canonical_link_from_html(
+        &dom.document,
+        &target
+    );
+    Some(canonical_url::page_url(&target, canonical_link))
+}
+
 // Extract the textual content from HTML body, capturing potential errors
 fn extract_text_body(dom: &RcDom) -> Result<String, Box<dyn std::error::Error>> {
     match helper_functions::extract_text_from_html(dom) {
@@ -147,39 +236,27 @@ fn extract_text_body(dom: &RcDom) -> Result<String, Box<dyn std::error::Error>>
     }
 }
 
-// Extract content type from a header line
-fn extract_content_type(line: &str) -> Option<String> {
-    line.split(':')
-        .nth(1)
-        .map(|s| s.trim().to_string())
-}
-
-// Extract status code from a status line
-fn extract_status(line: &str) -> Option<usize> {
-    line.split_whitespace()
-        .nth(1)
-        .and_then(|s| s.parse().ok())
-}
-
 // Extract webpage title from HTML
 fn extract_title(dom: &RcDom) -> Option<String> {
     helper_functions::extract_title_from_html(&dom.document)
 }
 
-// Extract webpage title from HTML
-fn extract_description(dom: &RcDom) -> Option<String> {
-    helper_functions::extract_description_from_html(&dom.document)
+// Extract webpage description from HTML, or a snippet of the text body
+fn extract_description(dom: &RcDom, text_body: &str) -> Option<(String, DescriptionSource)> {
+    helper_functions::extract_description_from_html(&dom.document, text_body)
 }
 
 #[derive(Debug)]
 pub enum ParseError {
-    BodyEncodingError(String),
+    MalformedHttpResponse(String),
+    UndecodableBody(String),
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParseError::BodyEncodingError(msg) => write!(f, "Body encoding error: {}", msg),
+            ParseError::MalformedHttpResponse(msg) => write!(f, "Malformed HTTP response: {}", msg),
+            ParseError::UndecodableBody(msg) => write!(f, "Undecodable response body: {}", msg),
         }
     }
 }
@@ -0,0 +1,67 @@
+use warc::{ WarcHeader, Record, BufferedBody };
+use whichlang::{ detect_language, Lang };
+
+use crate::analyzer::{ self, Analyzer };
+use crate::canonical_url;
+use crate::handle_warc::webpage::{ analyze_field, FieldTerms, Webpage };
+use crate::helper_functions::{ self, DescriptionSource };
+use crate::simhash;
+use crate::tokenizer::Token;
+
+// WET text starts with the page title on its own line, which can be long when a page has none
+const MAX_TITLE_LENGTH: usize = 200;
+
+// Build a webpage from a WET conversion record, whose body is the UTF-8 text of the page.
+// There is no HTML, so the title is the first line of text and the description is a snippet.
+pub fn parse_conversion(record: &Record<BufferedBody>) -> Option<Webpage> {
+    let warc_target_uri: String = record.header(WarcHeader::TargetURI)?.to_string();
+    let canonical_url: String = canonical_url::canonicalise_str(&warc_target_uri)?;
+
+    let text_body: String = String::from_utf8_lossy(record.body()).trim().to_string();
+    if text_body.is_empty() {
+        return None;
+    }
+
+    let lang: Lang = detect_language(&text_body);
+    if !analyzer::is_accepted_language(lang) {
+        return None;
+    }
+    let analyzer: &Analyzer = analyzer::analyzer_for(lang);
+
+    let title: String = first_line(&text_body);
+    let description: Option<String> = helper_functions::extract_snippet(&text_body);
+    let lemmatised_text: Vec<Token> = analyzer.analyze(&text_body);
+    let field_terms: FieldTerms = FieldTerms {
+        title: analyze_field(analyzer, &title),
+        description: analyze_field(analyzer, description.as_deref().unwrap_or_default()),
+        headings: Vec::new(),
+        url: analyze_field(analyzer, &helper_functions::url_to_words(&canonical_url)),
+    };
+
+    Some(Webpage {
+        warc_date: record.header(WarcHeader::Date).map(|date| date.to_string()),
+        record_id: record.header(WarcHeader::RecordID).map(|record_id| record_id.to_string()),
+        warc_target_uri: Some(warc_target_uri),
+        canonical_url: Some(canonical_url),
+        content_type: record.header(WarcHeader::ContentType).map(|content_type| content_type.to_string()),
+        charset: Some("UTF-8".to_string()),
+        title: Some(title),
+        description_source: description.as_ref().map(|_| DescriptionSource::Snippet),
+        description,
+        simhash: simhash::fingerprint(&lemmatised_text),
+        lemmatised_text: Some(lemmatised_text),
+        full_text: Some(text_body.clone()),
+        text_body: Some(text_body),
+        lang: Some(lang),
+        field_terms: Some(field_terms),
+        ..Default::default()
+    })
+}
+
+fn first_line(text: &str) -> String {
+    let line: &str = text.lines().next().unwrap_or_default().trim();
+    match line.char_indices().nth(MAX_TITLE_LENGTH) {
+        Some((end, _)) => line[..end].to_string(),
+        None => line.to_string(),
+    }
+}
@@ -5,10 +5,8 @@ use std::collections::HashSet;
 use std::path::Path;
 use lazy_static::lazy_static;
 use url::Url;
-use std::collections::HashMap;
-use once_cell::sync::Lazy;
-use regex::Regex;
 
+use crate::canonical_url;
 use markup5ever_rcdom::{ Handle, NodeData, RcDom };
 
 pub fn extract_domain_from_string(url: &str) -> Option<String> {
@@ -40,39 +38,6 @@ pub fn fetch_lines(num: usize, file_path: &str) -> Result<Vec<String>, Box<dyn E
     Ok(lines)
 }
 
-// Global static for storing the lemma mappings.
-static LEMMA_MAP: Lazy<HashMap<String, String>> = Lazy::new(|| {
-    let mut map = HashMap::new();
-    let lines = fetch_lines(0, "lemmatised_words.txt").expect("Failed to read lemma file");
-
-    let re = Regex::new(r"^([^/]+)[^->]*->(.+)$").unwrap();
-    for entry in lines {
-        if let Some(captures) = re.captures(&entry) {
-            let lemma = captures[1].trim().to_string();
-            let words: Vec<&str> = captures[2]
-                .split(',')
-                .map(|word| word.trim())
-                .collect();
-            for word in words {
-                map.insert(word.to_string(), lemma.clone());
-            }
-        }
-    }
-    map
-});
-
-// Function to lemmatize a given string.
-pub fn lemmatise_string(text: &str) -> Vec<String> {
-    let re: Regex = Regex::new(r"[^a-zA-Z0-9\s]").unwrap(); // regex to match non-word characters
-    let text_no_punct = re.replace_all(text, ""); // remove punctuation
-    text_no_punct
-        .split_whitespace()
-        .map(|word: &str| {
-            LEMMA_MAP.get(word.to_lowercase().as_str()).unwrap_or(&word.to_string()).clone()
-        })
-        .collect()
-}
-
 // Optimized function signature to pass tags set as parameter
 pub fn extract_text_from_html(dom: &RcDom) -> Result<String, Box<dyn Error>> {
     let mut visible_text: String = String::new();
@@ -127,15 +92,28 @@ lazy_static! {
     };
 }
 
-pub fn extract_links_from_html(dom: &RcDom, base_url: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let mut links: Vec<String> = Vec::new();
+// A link found in a page, with the text and attributes of its `<a>` element
+#[derive(Debug, Clone, Default)]
+pub struct Link {
+    pub url: String,
+    pub anchor_text: String,
+    pub title: Option<String>,
+    pub rel: Option<String>,
+    // Set for `rel="nofollow"`, `ugc` and `sponsored` links and on pages marked nofollow
+    pub nofollow: bool,
+    // Analyzed anchor text and title, credited to the target page
+    pub anchor_terms: Vec<String>,
+}
+
+pub fn extract_links_from_html(dom: &RcDom, base_url: &str) -> Result<Vec<Link>, Box<dyn Error>> {
+    let mut links: Vec<Link> = Vec::new();
     let base: Url = Url::parse(base_url)?;
 
     extract_links(&dom.document, &mut links, &base);
     Ok(links)
 }
 
-fn extract_links(handle: &Handle, links: &mut Vec<String>, base: &Url) {
+fn extract_links(handle: &Handle, links: &mut Vec<Link>, base: &Url) {
     let node = handle;
     match node.data {
         NodeData::Document => {
@@ -146,14 +124,30 @@ fn extract_links(handle: &Handle, links: &mut Vec<String>, base: &Url) {
         NodeData::Element { ref name, ref attrs, .. } => {
             let tag_name = name.local.as_ref();
             if tag_name == "a" {
-                for attr in attrs.borrow().iter() {
-                    if attr.name.local.as_ref() == "href" {
-                        let href: String = attr.value.to_string();
-                        match base.join(&href) {
-                            Ok(full_url) => links.push(full_url.to_string()),
-                            Err(_) => links.push(href),
-                        }
-                    }
+                let attrs = attrs.borrow();
+                let attribute = |attr_name: &str| -> Option<String> {
+                    attrs
+                        .iter()
+                        .find(|attr| attr.name.local.as_ref() == attr_name)
+                        .map(|attr| collapse_whitespace(&attr.value))
+                        .filter(|value| !value.is_empty())
+                };
+
+                if let Some(href) = attribute("href") {
+                    let mut anchor_text: String = String::new();
+                    extract_visible_text(node, &mut anchor_text, &TAGS_WITH_LINE_BREAK);
+
+                    links.push(Link {
+                        url: match base.join(&href) {
+                            Ok(full_url) => canonical_url::canonicalise(&full_url).to_string(),
+                            Err(_) => href,
+                        },
+                        anchor_text: collapse_whitespace(&anchor_text),
+                        title: attribute("title"),
+                        rel: attribute("rel").map(|rel| rel.to_lowercase()),
+                        nofollow: false,
+                        anchor_terms: Vec::new(),
+                    });
                 }
             }
             for child in node.children.borrow().iter() {
@@ -164,19 +158,48 @@ fn extract_links(handle: &Handle, links: &mut Vec<String>, base: &Url) {
     }
 }
 
+// Find the `<link rel="canonical">` of a page, resolved against the page URL
+pub fn extract_canonical_link_from_html(node: &Handle, base: &Url) -> Option<Url> {
+    if let NodeData::Element { ref name, ref attrs, .. } = node.data {
+        if name.local.as_ref() == "link" {
+            let attrs = attrs.borrow();
+            let is_canonical: bool = attrs
+                .iter()
+                .any(|attr| {
+                    attr.name.local.as_ref() == "rel" &&
+                        attr.value
+                            .split_whitespace()
+                            .any(|rel| rel.eq_ignore_ascii_case("canonical"))
+                });
+            if is_canonical {
+                let href: Option<Url> = attrs
+                    .iter()
+                    .find(|attr| attr.name.local.as_ref() == "href")
+                    .and_then(|attr| base.join(attr.value.trim()).ok());
+                if href.is_some() {
+                    return href;
+                }
+            }
+        }
+    }
+    for child in node.children.borrow().iter() {
+        if let Some(href) = extract_canonical_link_from_html(child, base) {
+            return Some(href);
+        }
+    }
+    None
+}
+
 pub fn extract_title_from_html(node: &Handle) -> Option<String> {
-    match node.data {
-        NodeData::Element { ref name, .. } => {
-            let tag_name = name.local.as_ref();
-            if tag_name == "title" {
-                for child in node.children.borrow().iter() {
-                    if let NodeData::Text { ref contents } = child.data {
-                        return Some(contents.borrow().to_string());
-                    }
+    if let NodeData::Element { ref name, .. } = node.data {
+        let tag_name = name.local.as_ref();
+        if tag_name == "title" {
+            for child in node.children.borrow().iter() {
+                if let NodeData::Text { ref contents } = child.data {
+                    return Some(contents.borrow().to_string());
                 }
             }
         }
-        _ => {}
     }
     for child in node.children.borrow().iter() {
         if let Some(title) = extract_title_from_html(child) {
@@ -186,28 +209,150 @@ pub fn extract_title_from_html(node: &Handle) -> Option<String> {
     None
 }
 
-pub fn extract_description_from_html(node: &Handle) -> Option<String> {
-    match node.data {
-        NodeData::Element { ref name, .. } => {
-            let tag_name = name.local.as_ref();
-            if tag_name == "description" {
-                for child in node.children.borrow().iter() {
-                    if let NodeData::Text { ref contents } = child.data {
-                        return Some(contents.borrow().to_string());
-                    }
+// Where a webpage description was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DescriptionSource {
+    Meta,
+    OpenGraph,
+    Twitter,
+    Snippet,
+}
+
+impl DescriptionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DescriptionSource::Meta => "meta",
+            DescriptionSource::OpenGraph => "og",
+            DescriptionSource::Twitter => "twitter",
+            DescriptionSource::Snippet => "snippet",
+        }
+    }
+}
+
+// Minimum number of words for a paragraph to be used as a description snippet
+const MIN_SNIPPET_WORDS: usize = 10;
+const MAX_SNIPPET_LENGTH: usize = 300;
+
+// Extract the description from `<meta name="description">`, `og:description` or `twitter:description`,
+// falling back to a snippet of the first meaningful paragraph of the text body
+pub fn extract_description_from_html(
+    node: &Handle,
+    text_body: &str
+) -> Option<(String, DescriptionSource)> {
+    let meta_sources: [(&str, &str, DescriptionSource); 3] = [
+        ("name", "description", DescriptionSource::Meta),
+        ("property", "og:description", DescriptionSource::OpenGraph),
+        ("name", "twitter:description", DescriptionSource::Twitter),
+    ];
+    for (attr_name, attr_value, source) in meta_sources {
+        if let Some(description) = extract_meta_content(node, attr_name, attr_value) {
+            return Some((description, source));
+        }
+    }
+    extract_snippet(text_body).map(|snippet| (snippet, DescriptionSource::Snippet))
+}
+
+pub fn extract_robots_meta_from_html(node: &Handle) -> Option<String> {
+    extract_meta_content(node, "name", "robots")
+}
+
+// Find the non-empty content of the first `<meta>` element whose attribute matches, ignoring case
+fn extract_meta_content(node: &Handle, attr_name: &str, attr_value: &str) -> Option<String> {
+    if let NodeData::Element { ref name, ref attrs, .. } = node.data {
+        if name.local.as_ref() == "meta" {
+            let attrs = attrs.borrow();
+            let matches: bool = attrs
+                .iter()
+                .any(|attr| {
+                    attr.name.local.as_ref() == attr_name &&
+                        attr.value.trim().eq_ignore_ascii_case(attr_value)
+                });
+            if matches {
+                let content: Option<String> = attrs
+                    .iter()
+                    .find(|attr| attr.name.local.as_ref() == "content")
+                    .map(|attr| collapse_whitespace(&attr.value))
+                    .filter(|content| !content.is_empty());
+                if content.is_some() {
+                    return content;
                 }
             }
         }
-        _ => {}
     }
     for child in node.children.borrow().iter() {
-        if let Some(title) = extract_title_from_html(child) {
-            return Some(title);
+        if let Some(content) = extract_meta_content(child, attr_name, attr_value) {
+            return Some(content);
         }
     }
     None
 }
 
+// Use the first paragraph with enough words, or the first line of text if there is none
+pub fn extract_snippet(text_body: &str) -> Option<String> {
+    let paragraphs: Vec<String> = text_body
+        .lines()
+        .map(collapse_whitespace)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect();
+    let paragraph: &String = paragraphs
+        .iter()
+        .find(|paragraph| paragraph.split_whitespace().count() >= MIN_SNIPPET_WORDS)
+        .or_else(|| paragraphs.first())?;
+
+    if paragraph.len() <= MAX_SNIPPET_LENGTH {
+        return Some(paragraph.clone());
+    }
+    // Cut at the last word boundary that fits
+    let mut end: usize = MAX_SNIPPET_LENGTH;
+    while !paragraph.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut: &str = &paragraph[..end];
+    let cut: &str = cut.rfind(' ').map_or(cut, |space| &cut[..space]);
+    Some(format!("{}…", cut))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Collect the text of every h1 to h6 heading
+pub fn extract_headings_from_html(node: &Handle) -> Vec<String> {
+    let mut headings: Vec<String> = Vec::new();
+    extract_headings(node, &mut headings);
+    headings
+}
+
+fn extract_headings(node: &Handle, headings: &mut Vec<String>) {
+    if let NodeData::Element { ref name, .. } = node.data {
+        if matches!(name.local.as_ref(), "h1" | "h2" | "h3" | "h4" | "h5" | "h6") {
+            let mut heading: String = String::new();
+            extract_visible_text(node, &mut heading, &TAGS_WITH_LINE_BREAK);
+            let heading: String = collapse_whitespace(&heading);
+            if !heading.is_empty() {
+                headings.push(heading);
+            }
+            return;
+        }
+    }
+    for child in node.children.borrow().iter() {
+        extract_headings(child, headings);
+    }
+}
+
+// Split the host and path of a URL into words, e.g. "https://www.example.com/red-shoes" gives
+// "www example com red shoes"
+pub fn url_to_words(url: &str) -> String {
+    let Ok(parsed_url) = Url::parse(url) else {
+        return String::new();
+    };
+    format!("{} {}", parsed_url.host_str().unwrap_or_default(), parsed_url.path())
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub fn file_path_to_number(file_path: &Path) -> String {
     let file_path_string: String = file_path.to_string_lossy().to_string();
     let parts: Vec<&str> = file_path_string.split("-").collect();
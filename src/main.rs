@@ -1,28 +1,114 @@
-use env_logger;
+use dotenv::dotenv;
 
+use std::env;
+use std::collections::HashSet;
 use std::sync::Arc;
 use indicatif::MultiProgress;
 use tokio::task::JoinHandle;
-use tokio::sync::Semaphore;
+use tokio::sync::{ mpsc, Semaphore };
 use std::path::PathBuf;
 
-use num_cpus;
-
+mod analyzer;
+mod canonical_url;
 mod database;
+mod domain_filter;
 mod handle_warc;
 mod helper_functions;
+mod main_content;
+mod maintenance;
+mod pagerank;
+mod simhash;
+mod tokenizer;
+
+use database::Database;
+use domain_filter::DomainFilter;
+use handle_warc::archive_source::{ ArchiveSource, FetchedArchive };
+use handle_warc::{ InputKind, WebpageBatch };
+
+// Number of parsed batches that can wait for the database writer before reading pauses
+const PIPELINE_CAPACITY: usize = 4;
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
+    dotenv().ok();
+
+    // One connection pool is shared by every task for the whole run
+    let database: Database = match Database::connect().await {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // Maintenance commands run instead of processing WARC files
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(|command| command.as_str()) {
+        // `cargo run -- pagerank` scores the link graph
+        Some("pagerank") => {
+            if let Err(e) = pagerank::run(&database).await {
+                eprintln!("Error computing PageRank: {:?}", e);
+            }
+            return;
+        }
+        // `cargo run -- recompute-df` rebuilds document frequencies from `website_keywords`
+        Some("recompute-df") => {
+            if let Err(e) = maintenance::recompute_document_frequencies(&database).await {
+                eprintln!("Error recomputing document frequencies: {:?}", e);
+            }
+            return;
+        }
+        // `cargo run -- delete <url>...` removes pages from the index
+        Some("delete") => {
+            if let Err(e) = maintenance::delete_pages(&database, &args[1..]).await {
+                eprintln!("Error deleting pages: {:?}", e);
+            }
+            return;
+        }
+        // `cargo run -- bench-ingest <file>` compares the insert modes on a local file
+        Some("bench-ingest") => {
+            match args.get(1) {
+                Some(file) => {
+                    if let Err(e) = maintenance::bench_ingest(&database, file).await {
+                        eprintln!("Error benchmarking ingestion: {:?}", e);
+                    }
+                }
+                None => eprintln!("Usage: cargo run -- bench-ingest <file>"),
+            }
+            return;
+        }
+        _ => {}
+    }
 
-    // Load files to download
-    // let files: Vec<String> = helper_functions::fetch_lines(20, "warc.paths").unwrap();
-
-    let files: Vec<String> = database::fetch_files_to_process().await.unwrap();
+    // Process WARC files already on disk instead of downloading them when a directory or glob is given
+    let local_input: Option<String> = env::var("LOCAL_WARC_INPUT").ok();
+
+    // WARC files are parsed as HTML, WET and WAT files are indexed from their text or metadata
+    let kind: InputKind = InputKind::from_env().unwrap();
+
+    let files: Vec<String> = match &local_input {
+        Some(input) => {
+            let local_files: Vec<String> = handle_warc::find_local_warc_files(input, kind).unwrap();
+            database::fetch_local_files_to_process(&database, &local_files, kind).await.unwrap()
+        }
+        None => match database::fetch_files_to_process(&database, kind).await {
+            Ok(files) => files,
+            Err(e) => {
+                eprintln!("Error listing files to process: {}", e);
+                return;
+            }
+        },
+    };
 
     println!("Files left: {:?}", files.len());
 
+    // Remote files are fetched from the configured archive source
+    let source: Arc<ArchiveSource> = Arc::new(ArchiveSource::from_env().unwrap());
+
+    // The domain lists are loaded once and shared by every file
+    let domain_filter: Arc<DomainFilter> = Arc::new(DomainFilter::from_env().unwrap());
+
     // Create a vector to store the tasks
     let mut tasks: Vec<JoinHandle<()>> = Vec::new();
 
@@ -32,19 +118,6 @@ async fn main() {
     let num_cpus: usize = num_cpus::get_physical();
     let sem: Arc<Semaphore> = Arc::new(Semaphore::new(num_cpus));
 
-    // let file_path: PathBuf = PathBuf::from("warc_files/test.warc.gz");
-
-    // Read and process the WARC file
-    // match handle_warc::read_warc_file(&file_path, &multibar.clone()).await {
-    //     Ok(results) => {
-    //         match database::add_webpages(&results, &multibar, &file_path).await {
-    //             Ok(_) => {}
-    //             Err(e) => eprintln!("Error adding webpages to the database: {:?}", e),
-    //         }
-    //     }
-    //     Err(e) => eprintln!("Error reading file: {:?} - {:?}", "test.warc.gz", e),
-    // }
-
     for file in files {
         let permit: Result<
             tokio::sync::OwnedSemaphorePermit,
@@ -53,6 +126,10 @@ async fn main() {
 
         let file_clone: String = file.clone();
         let multibar: Arc<MultiProgress> = multibar.clone();
+        let is_local: bool = local_input.is_some();
+        let source: Arc<ArchiveSource> = source.clone();
+        let domain_filter: Arc<DomainFilter> = domain_filter.clone();
+        let database: Database = database.clone();
 
         let task: JoinHandle<()> = tokio::spawn(async move {
             let _permit: Result<
@@ -60,31 +137,76 @@ async fn main() {
                 tokio::sync::AcquireError
             > = permit;
 
-            // Download the WARC file
-            let file_path: PathBuf = handle_warc
-                ::download_warc_file(file_clone.as_str(), &multibar).await
-                .unwrap();
-            let mut results: Vec<handle_warc::webpage::Webpage> = Vec::new();
-            // Read and process the WARC file
-            match handle_warc::read_warc_file(file_path.as_path(), &multibar).await {
-                Ok(webpages) => {
-                    results = webpages;
+            // Fetch the WARC file unless it is already on disk
+            let archive: FetchedArchive = if is_local {
+                FetchedArchive { path: PathBuf::from(&file_clone), is_temporary: false }
+            } else {
+                match source.fetch(file_clone.as_str(), &multibar).await {
+                    Ok(archive) => archive,
+                    Err(e) => {
+                        eprintln!("Error fetching file: {:?} - {}", file_clone, e);
+                        return;
+                    }
                 }
-                Err(e) => eprintln!("Error reading file: {:?} - {:?}", file_clone, e),
-            }
-
-            match database::add_webpages(&results, &multibar, &file_path.as_path()).await {
-                Ok(_) => {
-                    match database::mark_file_as_processed(&file).await {
+            };
+            let file_path: PathBuf = archive.path;
+
+            // Records already written by an interrupted run of this file are skipped
+            let ingested_records: HashSet<String> = match
+                database::fetch_ingested_records(&database, &file).await
+            {
+                Ok(records) => records,
+                Err(e) => {
+                    eprintln!("Error fetching ingested records: {:?} - {:?}", file_clone, e);
+                    return;
+                }
+            };
+
+            // Read the WARC file on a blocking thread while the database writer consumes its batches
+            let (sender, receiver) = mpsc::channel::<WebpageBatch>(PIPELINE_CAPACITY);
+            let reader: JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>> = {
+                let file_path: PathBuf = file_path.clone();
+                let multibar: Arc<MultiProgress> = multibar.clone();
+                let database: Database = database.clone();
+                tokio::task::spawn_blocking(move || {
+                    handle_warc::read_warc_file(
+                        &file_path,
+                        kind,
+                        &domain_filter,
+                        &database,
+                        &ingested_records,
+                        &multibar,
+                        sender
+                    )
+                })
+            };
+
+            let added = database::add_webpages(
+                &database,
+                &file,
+                kind,
+                receiver,
+                &multibar,
+                file_path.as_path()
+            ).await;
+            let read = reader.await.unwrap();
+
+            match (read, added) {
+                (Ok(_), Ok(_)) => {
+                    match database::mark_file_as_processed(&database, &file).await {
                         Ok(_) => {}
                         Err(e) => eprintln!("Error marking file as processed: {:?}", e),
                     }
                 }
-                Err(e) => eprintln!("Error adding webpages to the database: {:?}", e),
+                // A failed writer also stops the reader, so report the writer's error first
+                (_, Err(e)) => eprintln!("Error adding webpages to the database: {:?}", e),
+                (Err(e), _) => eprintln!("Error reading file: {:?} - {:?}", file_clone, e),
             }
 
-            // Delete the file
-            std::fs::remove_file(&file_path).expect("Failed to delete file");
+            // Delete the downloaded file, local inputs are left untouched
+            if archive.is_temporary {
+                std::fs::remove_file(&file_path).expect("Failed to delete file");
+            }
         });
         tasks.push(task);
     }
@@ -0,0 +1,270 @@
+use markup5ever_rcdom::{ Handle, NodeData, RcDom };
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// Blocks outside `<article>` or `<main>` need at least this many words to count as content
+const MIN_BLOCK_WORDS: usize = 10;
+// Blocks where more than this share of the words are link text are treated as navigation
+const MAX_LINK_DENSITY: f64 = 0.33;
+// Main content shorter than this is not trusted, and the full text is used instead
+const MIN_MAIN_CONTENT_WORDS: usize = 25;
+
+// Elements whose text is never part of the main content
+const SKIPPED_TAGS: [&str; 13] = [
+    "head",
+    "script",
+    "style",
+    "noscript",
+    "template",
+    "svg",
+    "iframe",
+    "nav",
+    "aside",
+    "form",
+    "button",
+    "select",
+    "dialog",
+];
+const SKIPPED_ROLES: [&str; 6] = [
+    "navigation",
+    "banner",
+    "contentinfo",
+    "complementary",
+    "search",
+    "dialog",
+];
+const CONTENT_TAGS: [&str; 2] = ["article", "main"];
+// Page headers and footers, which are part of the content inside `<article>` or `<main>`
+const SECTIONING_TAGS: [&str; 2] = ["header", "footer"];
+const BLOCK_TAGS: [&str; 18] = [
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "li",
+    "ul",
+    "ol",
+    "dl",
+    "dt",
+    "dd",
+    "td",
+    "th",
+    "tr",
+    "table",
+    "pre",
+    "blockquote",
+    "figcaption",
+];
+const HEADING_TAGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+// Class names and ids used for page furniture rather than content
+static BOILERPLATE_NAMES: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)(^|[-_\s])(cookie|consent|banner|nav|navbar|menu|footer|sidebar|breadcrumbs?|share|social|advert|ads?|promo|popup|modal|newsletter|subscribe|related|comments?)($|[-_\s])"
+    ).unwrap()
+});
+
+// A run of text between block-level element boundaries
+#[derive(Default)]
+struct Block {
+    text: String,
+    words: usize,
+    link_words: usize,
+    is_heading: bool,
+    in_content: bool,
+}
+
+impl Block {
+    fn link_density(&self) -> f64 {
+        if self.words == 0 { 0.0 } else { (self.link_words as f64) / (self.words as f64) }
+    }
+
+    fn is_content(&self) -> bool {
+        if self.words == 0 || self.link_density() > MAX_LINK_DENSITY {
+            return false;
+        }
+        self.is_heading || self.in_content || self.words >= MIN_BLOCK_WORDS
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Context {
+    in_link: bool,
+    in_content: bool,
+    in_heading: bool,
+}
+
+// Extract the main content of a page, leaving out navigation, banners, footers and hidden elements.
+// Text is split into blocks at block-level elements, and a block is kept when it is inside
+// `<article>` or `<main>`, is a heading, or has enough words, as long as it isn't mostly links.
+// Returns None when too little content is found to be trusted.
+pub fn extract_main_content(dom: &RcDom) -> Option<String> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut current: Block = Block::default();
+    collect_blocks(&dom.document, &mut blocks, &mut current, Context::default());
+    blocks.push(current);
+
+    let content: Vec<&Block> = blocks
+        .iter()
+        .filter(|block| block.is_content())
+        .collect();
+    let words: usize = content
+        .iter()
+        .map(|block| block.words)
+        .sum();
+    if words < MIN_MAIN_CONTENT_WORDS {
+        return None;
+    }
+
+    Some(
+        content
+            .iter()
+            .map(|block| block.text.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn collect_blocks(node: &Handle, blocks: &mut Vec<Block>, current: &mut Block, context: Context) {
+    match node.data {
+        NodeData::Document => {
+            for child in node.children.borrow().iter() {
+                collect_blocks(child, blocks, current, context);
+            }
+        }
+        NodeData::Element { ref name, ref attrs, .. } => {
+            let tag_name: &str = name.local.as_ref();
+            let attrs = attrs.borrow();
+            let attribute = |attr_name: &str| -> Option<String> {
+                attrs
+                    .iter()
+                    .find(|attr| attr.name.local.as_ref() == attr_name)
+                    .map(|attr| attr.value.to_string())
+            };
+
+            let role: String = attribute("role").unwrap_or_default().to_lowercase();
+            let style: String = attribute("style").unwrap_or_default().to_lowercase().replace(' ', "");
+            let is_hidden: bool =
+                attribute("hidden").is_some() ||
+                attribute("aria-hidden").is_some_and(|value| value.trim() == "true") ||
+                style.contains("display:none") ||
+                style.contains("visibility:hidden");
+            // The page wrappers are never skipped, whatever their class names
+            let is_boilerplate: bool =
+                tag_name != "html" &&
+                tag_name != "body" &&
+                [attribute("class"), attribute("id")]
+                    .iter()
+                    .flatten()
+                    .any(|name| BOILERPLATE_NAMES.is_match(name));
+            let is_content: bool = CONTENT_TAGS.contains(&tag_name) || role == "main";
+
+            // Content containers are trusted even when their class names look like page furniture
+            if
+                SKIPPED_TAGS.contains(&tag_name) ||
+                (SECTIONING_TAGS.contains(&tag_name) && !context.in_content) ||
+                SKIPPED_ROLES.contains(&role.as_str()) ||
+                is_hidden ||
+                (is_boilerplate && !is_content)
+            {
+                return;
+            }
+
+            let context: Context = Context {
+                in_link: context.in_link || tag_name == "a",
+                in_content: context.in_content || is_content,
+                in_heading: context.in_heading || HEADING_TAGS.contains(&tag_name),
+            };
+            let is_block: bool = BLOCK_TAGS.contains(&tag_name) || HEADING_TAGS.contains(&tag_name);
+
+            if is_block {
+                blocks.push(std::mem::take(current));
+            }
+            for child in node.children.borrow().iter() {
+                collect_blocks(child, blocks, current, context);
+            }
+            if is_block {
+                blocks.push(std::mem::take(current));
+            } else if tag_name == "br" {
+                current.text.push(' ');
+            }
+        }
+        NodeData::Text { ref contents } => {
+            let text = contents.borrow();
+            let words: usize = text.split_whitespace().count();
+            if words == 0 {
+                return;
+            }
+            current.text.push_str(&text);
+            current.words += words;
+            if context.in_link {
+                current.link_words += words;
+            }
+            current.is_heading |= context.in_heading;
+            current.in_content |= context.in_content;
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    fn main_content(html: &str) -> Option<String> {
+        let dom: RcDom = parse_document(RcDom::default(), Default::default()).one(html);
+        extract_main_content(&dom)
+    }
+
+    #[test]
+    fn keeps_the_article_and_drops_page_furniture() {
+        let content: String = main_content(include_str!("../tests/fixtures/main_content/article.html")).unwrap();
+
+        assert_eq!(
+            content,
+            [
+                "Growing tomatoes on a balcony",
+                "Short intro inside the article.",
+                "Tomatoes grow well in large pots as long as they get at least six hours of direct sunlight every day.",
+                "Water the soil deeply in the morning and feed the plants with a liquid fertiliser once the first flowers appear.",
+            ].join("\n")
+        );
+        for dropped in ["script text", "site header", "Vegetables", "cookies", "Decorative", "Hidden text", "Sidebar", "Copyright"] {
+            assert!(!content.contains(dropped), "{} was kept", dropped);
+        }
+    }
+
+    #[test]
+    fn keeps_long_blocks_without_an_article() {
+        let content: String = main_content(include_str!("../tests/fixtures/main_content/no_article.html")).unwrap();
+
+        assert_eq!(
+            content,
+            [
+                "Repairing a bicycle puncture",
+                "Remove the wheel from the frame and use two tyre levers to lift one side of the tyre off the rim.",
+                "Pull out the inner tube, find the hole by pumping it up and listening, then roughen the area with sandpaper.",
+            ].join("\n")
+        );
+        // The menu by its id, the short share block, the list of links and the hidden paragraph
+        for dropped in ["Menu entries", "Share this", "rim brakes", "inline style"] {
+            assert!(!content.contains(dropped), "{} was kept", dropped);
+        }
+    }
+
+    #[test]
+    fn falls_back_when_too_little_content_is_found() {
+        assert_eq!(main_content(include_str!("../tests/fixtures/main_content/too_short.html")), None);
+    }
+
+    #[test]
+    fn needs_the_minimum_number_of_words() {
+        let page = |words: usize| format!("<html><body><main><p>{}</p></main></body></html>", vec!["word"; words].join(" "));
+
+        assert_eq!(main_content(&page(MIN_MAIN_CONTENT_WORDS - 1)), None);
+        assert_eq!(main_content(&page(MIN_MAIN_CONTENT_WORDS)), Some(vec!["word"; MIN_MAIN_CONTENT_WORDS].join(" ")));
+    }
+}
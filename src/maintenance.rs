@@ -0,0 +1,136 @@
+use colored::*;
+use indicatif::{ MultiProgress, ProgressDrawTarget };
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+use tokio::sync::mpsc;
+
+use crate::canonical_url;
+use crate::database::{ self, Database, DocumentFrequencyDrift };
+use crate::database::bulk_load::InsertMode;
+use crate::domain_filter::DomainFilter;
+use crate::handle_warc::{ self, InputKind, WebpageBatch };
+use crate::handle_warc::webpage::Webpage;
+
+// Times each insert mode is run by `bench-ingest`, keeping the fastest
+const BENCH_ROUNDS: usize = 3;
+
+// Rebuild `keywords.documents_containing_word` and report how far it had drifted
+pub async fn recompute_document_frequencies(database: &Database) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let start: Instant = Instant::now();
+    let drift: DocumentFrequencyDrift = database::recompute_document_frequencies(database).await?;
+
+    println!(
+        "{} | {} | {} | {}",
+        "Recomputed document frequencies".green().bold(),
+        format!("Keywords corrected: {}/{}", drift.drifted_count, drift.keyword_count).yellow(),
+        format!(
+            "Overcounted: {}, undercounted: {}",
+            drift.overcounted_count,
+            drift.drifted_count - drift.overcounted_count
+        ).yellow(),
+        format!("Total drift: {} documents", drift.total_drift).yellow()
+    );
+    for (word, stored, rebuilt) in &drift.largest_drifts {
+        println!("  {}: {} stored, {} counted", word, stored, rebuilt);
+    }
+    println!(
+        "{}",
+        format!("Time taken overall: {:.2}s", start.elapsed().as_secs_f64()).cyan()
+    );
+    Ok(())
+}
+
+// Remove the pages stored under these URLs, which are canonicalised first
+pub async fn delete_pages(database: &Database, urls: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let urls: Vec<String> = urls
+        .iter()
+        .map(|url| canonical_url::canonicalise_str(url).unwrap_or_else(|| url.clone()))
+        .collect();
+    let deleted: usize = database::delete_websites(database, &urls).await?;
+
+    println!(
+        "{} | {}",
+        "Deleted pages".green().bold(),
+        format!("Found {}/{}", deleted, urls.len()).yellow()
+    );
+    Ok(())
+}
+
+// Parse a local file once, then write its pages with each insert mode and compare the timings.
+// Every run is rolled back, so the database is left as it was.
+pub async fn bench_ingest(database: &Database, file: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let kind: InputKind = InputKind::from_env().map_err(|e| e.to_string())?;
+    let domain_filter: DomainFilter = DomainFilter::from_env().map_err(|e| e.to_string())?;
+    let file_path: PathBuf = PathBuf::from(file);
+
+    // The reader is the same as for a normal run, its batches are only collected here
+    let (sender, mut receiver) = mpsc::channel::<WebpageBatch>(1);
+    let reader = {
+        let database: Database = database.clone();
+        tokio::task::spawn_blocking(move || {
+            let multibar: Arc<MultiProgress> = Arc::new(
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+            );
+            handle_warc::read_warc_file(
+                &file_path,
+                kind,
+                &domain_filter,
+                &database,
+                &HashSet::new(),
+                &multibar,
+                sender
+            )
+        })
+    };
+    let mut webpages: Vec<Webpage> = Vec::new();
+    while let Some(batch) = receiver.recv().await {
+        webpages.extend(batch.webpages);
+    }
+    reader.await??;
+
+    println!("{}", format!("Parsed {} webpages from {}", webpages.len(), file).cyan());
+
+    let mut fastest: Vec<(InsertMode, usize, Duration)> = Vec::new();
+    for mode in [InsertMode::Values, InsertMode::Copy] {
+        let mut best: Option<(usize, Duration)> = None;
+        for _ in 0..BENCH_ROUNDS {
+            // A mode that can't write every page is reported and left out of the comparison
+            let (pages, elapsed) = match database::time_insert_mode(database, &webpages, kind, mode).await {
+                Ok(timing) => timing,
+                Err(e) => {
+                    eprintln!("Insert mode {} failed: {}", mode.as_str(), e);
+                    best = None;
+                    break;
+                }
+            };
+            if best.is_none_or(|(_, best_elapsed)| elapsed < best_elapsed) {
+                best = Some((pages, elapsed));
+            }
+        }
+        if let Some((pages, elapsed)) = best {
+            println!(
+                "{} | {} | {}",
+                format!("Insert mode {}", mode.as_str()).green().bold(),
+                format!("Webpages: {}", pages).yellow(),
+                format!(
+                    "Fastest of {}: {:.2}s, {:.1} webpages/s",
+                    BENCH_ROUNDS,
+                    elapsed.as_secs_f64(),
+                    (pages as f64) / elapsed.as_secs_f64()
+                ).cyan()
+            );
+            fastest.push((mode, pages, elapsed));
+        }
+    }
+
+    if let [(_, _, values), (_, _, copy)] = fastest.as_slice() {
+        println!(
+            "{}",
+            format!("copy took {:.2}x the time of values", copy.as_secs_f64() / values.as_secs_f64()).yellow()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use colored::*;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::time::Instant;
+use url::Url;
+
+use crate::database::{ self, Database };
+
+const DEFAULT_DAMPING: f64 = 0.85;
+const DEFAULT_TOLERANCE: f64 = 1e-6;
+const DEFAULT_MAX_ITERATIONS: usize = 100;
+
+pub struct PageRankOptions {
+    // Probability of following a link rather than jumping to a random page
+    pub damping: f64,
+    // Iteration stops once the scores change by less than this in total (L1 norm)
+    pub tolerance: f64,
+    pub max_iterations: usize,
+}
+
+impl PageRankOptions {
+    // Read `PAGERANK_DAMPING`, `PAGERANK_TOLERANCE` and `PAGERANK_MAX_ITERATIONS`
+    pub fn from_env() -> Self {
+        PageRankOptions {
+            damping: env_or("PAGERANK_DAMPING", DEFAULT_DAMPING),
+            tolerance: env_or("PAGERANK_TOLERANCE", DEFAULT_TOLERANCE),
+            max_iterations: env_or("PAGERANK_MAX_ITERATIONS", DEFAULT_MAX_ITERATIONS),
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Compute PageRank over the indexed websites and store the page and host scores
+pub async fn run(database: &Database) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let options: PageRankOptions = PageRankOptions::from_env();
+    let start: Instant = Instant::now();
+
+    let (websites, links) = database::fetch_link_graph(database).await?;
+    println!(
+        "{}",
+        format!("Loaded {} websites and {} links", websites.len(), links.len()).cyan()
+    );
+
+    // Websites are numbered by their position so the graph can be stored in plain vectors
+    let index_of: HashMap<i32, usize> = websites
+        .iter()
+        .enumerate()
+        .map(|(index, (id, _))| (*id, index))
+        .collect();
+    let edges: Vec<(usize, usize)> = links
+        .iter()
+        .filter_map(|(source, target)| Some((*index_of.get(source)?, *index_of.get(target)?)))
+        .collect();
+
+    let (scores, iterations) = compute_pagerank(websites.len(), &edges, &options);
+    println!(
+        "{}",
+        format!("PageRank finished after {} iterations", iterations).cyan()
+    );
+
+    let page_scores: Vec<(i32, f64)> = websites
+        .iter()
+        .zip(&scores)
+        .map(|((id, _), score)| (*id, *score))
+        .collect();
+    database::store_pagerank(database, &page_scores).await?;
+
+    let host_scores: Vec<(String, f64, i32)> = host_rollup(&websites, &scores);
+    database::store_host_pagerank(database, &host_scores).await?;
+
+    println!(
+        "{} | {} | {}",
+        "Stored PageRank".green().bold(),
+        format!("Hosts: {}", host_scores.len()).yellow(),
+        format!("Time taken overall: {:.2}s", start.elapsed().as_secs_f64()).cyan()
+    );
+    Ok(())
+}
+
+// Iterative PageRank over `node_count` nodes, returning the scores and the number of iterations.
+// Duplicate edges and self links are ignored. Dangling nodes spread their score over every node,
+// so the scores always sum to one.
+pub fn compute_pagerank(
+    node_count: usize,
+    edges: &[(usize, usize)],
+    options: &PageRankOptions
+) -> (Vec<f64>, usize) {
+    if node_count == 0 {
+        return (Vec::new(), 0);
+    }
+
+    let mut edges: Vec<(usize, usize)> = edges
+        .iter()
+        .copied()
+        .filter(|(source, target)| source != target)
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+
+    let mut out_degree: Vec<usize> = vec![0; node_count];
+    for (source, _) in &edges {
+        out_degree[*source] += 1;
+    }
+
+    let n: f64 = node_count as f64;
+    let mut scores: Vec<f64> = vec![1.0 / n; node_count];
+    let mut iterations: usize = 0;
+
+    while iterations < options.max_iterations {
+        iterations += 1;
+
+        let dangling_score: f64 = scores
+            .iter()
+            .zip(&out_degree)
+            .filter(|(_, degree)| **degree == 0)
+            .map(|(score, _)| score)
+            .sum();
+        let base: f64 = (1.0 - options.damping) / n + (options.damping * dangling_score) / n;
+
+        let mut next: Vec<f64> = vec![base; node_count];
+        for (source, target) in &edges {
+            next[*target] += (options.damping * scores[*source]) / (out_degree[*source] as f64);
+        }
+
+        let change: f64 = scores
+            .iter()
+            .zip(&next)
+            .map(|(old, new)| (old - new).abs())
+            .sum();
+        scores = next;
+
+        if change < options.tolerance {
+            break;
+        }
+    }
+
+    (scores, iterations)
+}
+
+// Sum page scores per host, returning the host, its score and its number of pages
+fn host_rollup(websites: &[(i32, String)], scores: &[f64]) -> Vec<(String, f64, i32)> {
+    let mut hosts: HashMap<String, (f64, i32)> = HashMap::new();
+    for ((_, url), score) in websites.iter().zip(scores) {
+        let host: Option<String> = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_lowercase()));
+        if let Some(host) = host {
+            let entry: &mut (f64, i32) = hosts.entry(host).or_insert((0.0, 0));
+            entry.0 += score;
+            entry.1 += 1;
+        }
+    }
+    hosts
+        .into_iter()
+        .map(|(host, (score, page_count))| (host, score, page_count))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(tolerance: f64, max_iterations: usize) -> PageRankOptions {
+        PageRankOptions { damping: DEFAULT_DAMPING, tolerance, max_iterations }
+    }
+
+    // A cycle 0 -> 1 -> 2 -> 0, where 2 also links to the dangling node 3
+    const CYCLE_WITH_DANGLING_NODE: [(usize, usize); 4] = [
+        (0, 1),
+        (1, 2),
+        (2, 0),
+        (2, 3),
+    ];
+
+    #[test]
+    fn scores_a_cycle_with_a_dangling_node() {
+        let (scores, _) = compute_pagerank(4, &CYCLE_WITH_DANGLING_NODE, &options(1e-12, 1000));
+
+        let expected: [f64; 4] = [0.213762154076, 0.264622288706, 0.307853403141, 0.213762154076];
+        for (score, expected) in scores.iter().zip(expected) {
+            assert!((score - expected).abs() < 1e-9, "{} != {}", score, expected);
+        }
+        assert!((scores.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ignores_duplicate_edges_and_self_links() {
+        let mut edges: Vec<(usize, usize)> = CYCLE_WITH_DANGLING_NODE.to_vec();
+        edges.extend([(0, 1), (1, 1), (3, 3)]);
+
+        let (scores, _) = compute_pagerank(4, &edges, &options(1e-12, 1000));
+        let (expected, _) = compute_pagerank(4, &CYCLE_WITH_DANGLING_NODE, &options(1e-12, 1000));
+        assert_eq!(scores, expected);
+    }
+
+    #[test]
+    fn stops_once_the_change_is_below_the_tolerance() {
+        // A plain cycle is already at its fixed point, so the first iteration changes nothing
+        let (scores, iterations) = compute_pagerank(4, &[(0, 1), (1, 2), (2, 3), (3, 0)], &options(1e-6, 100));
+        assert_eq!(iterations, 1);
+        assert!(scores.iter().all(|score| (score - 0.25).abs() < 1e-12));
+
+        let (_, loose) = compute_pagerank(4, &CYCLE_WITH_DANGLING_NODE, &options(1e-3, 1000));
+        let (_, tight) = compute_pagerank(4, &CYCLE_WITH_DANGLING_NODE, &options(1e-9, 1000));
+        assert!(loose < tight);
+        assert!(tight < 1000);
+
+        let (_, capped) = compute_pagerank(4, &CYCLE_WITH_DANGLING_NODE, &options(1e-12, 3));
+        assert_eq!(capped, 3);
+    }
+
+    #[test]
+    fn empty_graph_has_no_scores() {
+        assert_eq!(compute_pagerank(0, &[], &options(1e-6, 100)), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn rolls_scores_up_per_host() {
+        let websites: Vec<(i32, String)> = vec![
+            (1, "https://example.com/".to_string()),
+            (2, "https://example.com/about".to_string()),
+            (3, "https://EXAMPLE.org/".to_string()),
+            (4, "not a url".to_string())
+        ];
+        let mut hosts: Vec<(String, f64, i32)> = host_rollup(&websites, &[0.25, 0.5, 0.125, 0.125]);
+        hosts.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(hosts, vec![("example.com".to_string(), 0.75, 2), ("example.org".to_string(), 0.125, 1)]);
+    }
+}
@@ -0,0 +1,97 @@
+use log::warn;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::env;
+
+use crate::tokenizer::Token;
+
+// Number of consecutive words hashed together
+const SHINGLE_SIZE: usize = 3;
+// Pages with fewer words than this are too short to fingerprint reliably
+const MIN_FINGERPRINT_WORDS: usize = 20;
+// The fingerprint is split into this many bands of 16 bits for the lookup
+const BAND_COUNT: u32 = 4;
+// Fingerprints differing in at most this many bits are near-duplicates. With four bands, any two
+// such fingerprints share at least one band exactly.
+pub const MAX_DISTANCE: u32 = BAND_COUNT - 1;
+
+// What to do with a page whose fingerprint matches one already stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NearDuplicateMode {
+    // Store every page as usual
+    Off,
+    // Store the page with `duplicate_of` pointing at the first copy, but without keywords
+    Link,
+    // Don't store the page
+    Skip,
+}
+
+// Read from `NEAR_DUPLICATE_MODE` as `off`, `link` or `skip`, defaulting to `link`
+pub static NEAR_DUPLICATE_MODE: Lazy<NearDuplicateMode> = Lazy::new(|| {
+    match env::var("NEAR_DUPLICATE_MODE").unwrap_or_default().to_lowercase().as_str() {
+        "off" => NearDuplicateMode::Off,
+        "skip" => NearDuplicateMode::Skip,
+        "link" | "" => NearDuplicateMode::Link,
+        other => {
+            warn!("Unknown NEAR_DUPLICATE_MODE {}, using link", other);
+            NearDuplicateMode::Link
+        }
+    }
+});
+
+// SimHash of the word shingles of a text, weighted by how often each shingle appears
+pub fn fingerprint(tokens: &[Token]) -> Option<u64> {
+    if tokens.len() < MIN_FINGERPRINT_WORDS {
+        return None;
+    }
+
+    let mut shingle_counts: HashMap<u64, i64> = HashMap::new();
+    for window in tokens.windows(SHINGLE_SIZE) {
+        let shingle: String = window
+            .iter()
+            .map(|token| token.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+        *shingle_counts.entry(fnv1a(shingle.as_bytes())).or_insert(0) += 1;
+    }
+
+    let mut weights: [i64; 64] = [0; 64];
+    for (hash, count) in shingle_counts {
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if (hash >> bit) & 1 == 1 {
+                *weight += count;
+            } else {
+                *weight -= count;
+            }
+        }
+    }
+
+    Some(
+        weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0u64, |fingerprint, (bit, _)| fingerprint | (1 << bit))
+    )
+}
+
+// Split a fingerprint into tagged bands, so `(band << 16) | value` can be matched with an index
+pub fn bands(fingerprint: u64) -> Vec<i32> {
+    (0..BAND_COUNT)
+        .map(|band| {
+            let value: u64 = (fingerprint >> (band * 16)) & 0xffff;
+            ((band as i32) << 16) | (value as i32)
+        })
+        .collect()
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// FNV-1a, used instead of the std hasher because fingerprints are stored and must stay stable
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,117 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::UnicodeSegmentation;
+
+// A normalised word along with its word position and the byte range it came from in the original text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub position: usize,
+    // Byte offsets aren't used by the indexer yet, they are kept for snippets and highlighting
+    #[allow(dead_code)]
+    pub start: usize,
+    #[allow(dead_code)]
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenizerOptions {
+    // Strip accents so that "café" and "cafe" become the same token
+    pub fold_diacritics: bool,
+}
+
+// Split text into words using Unicode word boundaries (UAX #29).
+// Positions count every word, so they keep their gaps when later stages drop stopwords.
+// Hyphenated words are split into their parts, contractions such as "don't" are kept whole,
+// possessive "'s" is dropped and digit group separators are removed from numbers.
+pub fn tokenize(text: &str, options: &TokenizerOptions) -> Vec<Token> {
+    text.unicode_word_indices()
+        .filter_map(|(start, word)| {
+            let normalised: String = normalise_word(word, options);
+            if normalised.is_empty() { None } else { Some((start, word, normalised)) }
+        })
+        .enumerate()
+        .map(|(position, (start, word, normalised))| Token {
+            text: normalised,
+            position,
+            start,
+            end: start + word.len(),
+        })
+        .collect()
+}
+
+// Words that form a contraction rather than a possessive when followed by "'s"
+const S_CONTRACTIONS: [&str; 12] = [
+    "he", "here", "how", "it", "let", "she", "that", "there", "what", "when", "where", "who",
+];
+
+fn normalise_word(word: &str, options: &TokenizerOptions) -> String {
+    // Case folding, with typographic apostrophes treated like ASCII ones
+    let mut normalised: String = word.to_lowercase().replace(['\u{2019}', '\u{02bc}'], "'");
+
+    // Possessive "'s" is dropped, but contractions such as "it's" or "let's" are kept whole
+    if let Some(stripped) = normalised.strip_suffix("'s") {
+        if !S_CONTRACTIONS.contains(&stripped) {
+            normalised = stripped.to_string();
+        }
+    }
+
+    // Numbers such as "1,000" are indexed without group separators
+    if normalised.starts_with(|c: char| c.is_ascii_digit()) && normalised.contains(',') {
+        normalised = normalised.replace(',', "");
+    }
+
+    if options.fold_diacritics {
+        normalised = normalised
+            .nfd()
+            .filter(|c| !is_combining_mark(*c))
+            .nfc()
+            .collect();
+    }
+
+    normalised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str, fold_diacritics: bool) -> Vec<String> {
+        tokenize(text, &TokenizerOptions { fold_diacritics })
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    }
+
+    #[test]
+    fn splits_hyphenated_words_into_their_parts() {
+        assert_eq!(words("State-of-the-art tools", false), ["state", "of", "the", "art", "tools"]);
+    }
+
+    #[test]
+    fn keeps_contractions_and_drops_possessives() {
+        assert_eq!(
+            words("Don't say it's John's or Let\u{2019}s go", false),
+            ["don't", "say", "it's", "john", "or", "let's", "go"]
+        );
+    }
+
+    #[test]
+    fn removes_digit_group_separators_from_numbers() {
+        assert_eq!(words("1,000,000 people and 3.14 pies", false), ["1000000", "people", "and", "3.14", "pies"]);
+    }
+
+    #[test]
+    fn folds_diacritics_only_when_asked() {
+        assert_eq!(words("Café Ångström", false), ["café", "ångström"]);
+        assert_eq!(words("Café Ångström", true), ["cafe", "angstrom"]);
+    }
+
+    #[test]
+    fn positions_and_offsets_follow_the_original_text() {
+        let tokens: Vec<Token> = tokenize("a café!", &TokenizerOptions::default());
+
+        assert_eq!(tokens[1].position, 1);
+        assert_eq!(&"a café!"[tokens[1].start..tokens[1].end], "café");
+    }
+}